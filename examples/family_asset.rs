@@ -0,0 +1,65 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+use bevy::prelude::*;
+use bevy_family::*;
+
+#[derive(Component, Clone, Default, Reflect)]
+struct Building {
+    floors: u32,
+}
+
+#[derive(Component, Clone, Default, Reflect)]
+struct Level;
+
+#[derive(Component, Clone, Default, Reflect)]
+struct Room {
+    area: f32,
+}
+
+fn main() {
+    App::new()
+        .add_plugins((MinimalPlugins, AssetPlugin::default()))
+        .add_plugins(FamilyPlugin::<String>::default())
+        .add_plugins(FamilyAssetPlugin::<Building, String>::default())
+        .add_plugins(FamilyAssetPlugin::<Level, String>::default())
+        .add_plugins(FamilyAssetPlugin::<Room, String>::default())
+        .add_event::<CudEvent<Building, String>>()
+        .add_event::<CudEvent<Level, String>>()
+        .add_event::<CudEvent<Room, String>>()
+        .add_systems(Startup, load)
+        .add_systems(Update, (cud_bundle::<Building, String>, cud_bundle::<Level, String>, cud_bundle::<Room, String>).chain())
+        .add_systems(Update, print_lineage)
+        .run();
+}
+
+fn load(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.send_event(SpawnFamilyAsset::<String> {
+        handle: asset_server.load("tower.family.ron"),
+        parent_path: None,
+    });
+}
+
+fn print_lineage(lineage: Res<Lineage<String>>, mut exit: EventWriter<AppExit>) {
+    if lineage.histories.len() == 4 {
+        for history in &lineage.histories {
+            println!("{:?} {:?} under {:?}: {:?}", history.action, history.child_identifier, history.parent_identifier, history.result);
+        }
+        exit.send(AppExit::Success);
+    }
+}
@@ -0,0 +1,57 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+use bevy::prelude::*;
+use bevy_family::*;
+
+#[derive(Component, Clone)]
+struct Building;
+
+#[derive(Component, Clone)]
+struct Room;
+
+fn main() {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_plugins(FamilyPlugin::<String>::default())
+        .add_event::<CudEvent<Building, String>>()
+        .add_event::<CudEvent<Room, String>>()
+        .add_systems(Update, (cud_bundle::<Building, String>, cud_bundle::<Room, String>).chain());
+
+    app.world_mut().send_event(CudEvent::create_parent("Building".to_string(), Building));
+    app.update();
+    app.world_mut().send_event(CudEvent::create_child("Building".to_string(), "Room".to_string(), Room));
+    app.world_mut().send_event(CudEvent::create_child("Nowhere".to_string(), "Room".to_string(), Room));
+    app.update();
+
+    let lineage = app.world().resource::<Lineage<String>>();
+
+    // JSON round trip
+    let json = serde_json::to_string_pretty(lineage).unwrap();
+    let from_json: Lineage<String> = serde_json::from_str(&json).unwrap();
+    assert_eq!(json, serde_json::to_string_pretty(&from_json).unwrap());
+    println!("{}", json);
+
+    // RON round trip
+    let ron = ron::ser::to_string_pretty(lineage, ron::ser::PrettyConfig::default()).unwrap();
+    let from_ron: Lineage<String> = ron::from_str(&ron).unwrap();
+    assert_eq!(ron, ron::ser::to_string_pretty(&from_ron, ron::ser::PrettyConfig::default()).unwrap());
+    println!("{}", ron);
+
+    assert!(from_json.histories == lineage.histories && from_ron.histories == lineage.histories);
+}
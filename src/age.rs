@@ -0,0 +1,119 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+use bevy_ecs::prelude::*;
+use bevy_ecs::system::SystemParam;
+use bevy_hierarchy::prelude::*;
+
+use std::time::Duration;
+
+use crate::{BiologicalClock, FamilyQuery, Identifier};
+
+/// Distribution of the ages of a group of entities.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AgeHistogram {
+    /// Width of every bucket.
+    pub bucket_width: Duration,
+    /// Number of entities in every bucket, the last bucket also counts the entities that are older.
+    pub counts: Vec<usize>,
+    pub total: usize,
+    pub youngest: Option<Duration>,
+    pub oldest: Option<Duration>,
+    pub mean: Option<Duration>,
+}
+
+impl AgeHistogram {
+    /// Iterate over the lower bound of the age of every bucket with its number of entities.
+    pub fn buckets(&self) -> impl Iterator<Item = (Duration, usize)> + '_ {
+        self.counts.iter().enumerate().map(|(index, count)| (self.bucket_width * index as u32, *count))
+    }
+}
+
+/// System parameter that answers questions about the ages of the entities with `T`
+///
+/// Every question can be scoped to the descendants of an ancestor, by its identifier `V`.
+#[derive(SystemParam)]
+pub struct AgeQuery<'w, 's, T, V>
+where
+    T: Component,
+    V: Send + Sync + 'static,
+{
+    members: Query<'w, 's, (Entity, &'static BiologicalClock), With<T>>,
+    parents: Query<'w, 's, &'static Parent>,
+    family: FamilyQuery<'w, 's, V>,
+}
+
+impl<T, V> AgeQuery<'_, '_, T, V>
+where
+    T: Component,
+    V: PartialEq + Send + Sync + 'static,
+{
+    /// Iterate over the entities with their age, under the ancestor if there is one.
+    ///
+    /// Nothing is returned if the ancestor does not exist.
+    pub fn iter(&self, ancestor: Option<&Identifier<V>>) -> impl Iterator<Item = (Entity, Duration)> + '_ {
+        let ancestor = ancestor.map(|ancestor| self.family.get(ancestor));
+        self.members
+            .iter()
+            .filter(move |(entity, _)| match ancestor {
+                None => true,
+                Some(None) => false,
+                Some(Some(ancestor)) => self.parents.iter_ancestors(*entity).any(|parent| parent == ancestor),
+            })
+            .map(|(entity, biological_clock)| (entity, biological_clock.age()))
+    }
+
+    /// Get the oldest entity with its age, under the ancestor if there is one.
+    pub fn oldest(&self, ancestor: Option<&Identifier<V>>) -> Option<(Entity, Duration)> {
+        self.iter(ancestor).max_by_key(|(_, age)| *age)
+    }
+
+    /// Get the youngest entity with its age, under the ancestor if there is one.
+    pub fn youngest(&self, ancestor: Option<&Identifier<V>>) -> Option<(Entity, Duration)> {
+        self.iter(ancestor).min_by_key(|(_, age)| *age)
+    }
+
+    /// Count the entities that are older than the age, under the ancestor if there is one.
+    pub fn count_older_than(&self, ancestor: Option<&Identifier<V>>, age: Duration) -> usize {
+        self.iter(ancestor).filter(|(_, entity_age)| *entity_age > age).count()
+    }
+
+    /// Summarize the ages in buckets of the same width, under the ancestor if there is one.
+    pub fn histogram(&self, ancestor: Option<&Identifier<V>>, bucket_width: Duration, bucket_count: usize) -> AgeHistogram {
+        let mut histogram = AgeHistogram {
+            bucket_width,
+            counts: vec![0; bucket_count],
+            ..Default::default()
+        };
+        let mut sum = Duration::ZERO;
+        for (_, age) in self.iter(ancestor) {
+            if bucket_count > 0 {
+                let index = if bucket_width.is_zero() { bucket_count - 1 } else { ((age.as_nanos() / bucket_width.as_nanos()) as usize).min(bucket_count - 1) };
+                histogram.counts[index] += 1;
+            }
+            histogram.total += 1;
+            histogram.youngest = Some(histogram.youngest.map_or(age, |youngest| youngest.min(age)));
+            histogram.oldest = Some(histogram.oldest.map_or(age, |oldest| oldest.max(age)));
+            sum += age;
+        }
+        if histogram.total > 0 {
+            histogram.mean = Some(sum / histogram.total as u32);
+        }
+        histogram
+    }
+}
@@ -0,0 +1,256 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+use bevy_ecs::prelude::*;
+use bevy_ecs::system::{EntityCommands, SystemParam};
+use bevy_hierarchy::prelude::*;
+use bevy_log::prelude::*;
+
+use std::any::TypeId;
+use std::sync::Arc;
+
+use crate::{capture_ancestor, register_member, Action, BiologicalClock, ClockInsertion, FamilyMember, FamilyPath, FamilyQuery, Generation, Generations, History, Identifier, Lineage, Population};
+
+/// Function that inserts the bundle of a missing ancestor, given its identifier.
+pub type AncestorFactory<V> = Arc<dyn Fn(&mut EntityCommands, &Identifier<V>) + Send + Sync>;
+
+/// Resource with the bundles of the ancestors that are created by `CudEvent::create_with_ancestors`, by their depth.
+///
+/// Ancestors at a depth without a bundle are created with only their identifier, and a `BiologicalClock` if the entities of the event get one.
+/// Ancestors with a bundle get a `BiologicalClock` according to the `ClockInsertion` of their bundle.
+#[derive(Resource)]
+pub struct AncestorBundles<V> {
+    factories: Vec<Option<(TypeId, AncestorFactory<V>)>>,
+}
+
+impl<V> Default for AncestorBundles<V> {
+    fn default() -> Self {
+        Self { factories: Vec::new() }
+    }
+}
+
+impl<V> AncestorBundles<V> {
+    /// Create the missing ancestors at the depth with a copy of the bundle.
+    pub fn with_bundle<B>(self, depth: usize, bundle: B) -> Self
+    where
+        B: Bundle + Clone,
+        V: Send + Sync + 'static,
+    {
+        self.with_factory(depth, move |_| bundle.clone())
+    }
+
+    /// Create the missing ancestors at the depth with a bundle from the factory.
+    pub fn with_factory<B>(mut self, depth: usize, factory: impl Fn(&Identifier<V>) -> B + Send + Sync + 'static) -> Self
+    where
+        B: Bundle,
+        V: Send + Sync + 'static,
+    {
+        if self.factories.len() <= depth {
+            self.factories.resize_with(depth + 1, || None);
+        }
+        let factory: AncestorFactory<V> = Arc::new(move |entity_commands, identifier| {
+            entity_commands.insert((factory(identifier), FamilyMember::<B>::default())).queue(insert_biological_clock::<B>).queue(register_member::<B, V>);
+        });
+        self.factories[depth] = Some((TypeId::of::<B>(), factory));
+        self
+    }
+
+    /// Get the factory of the ancestors at the depth.
+    pub fn get_factory(&self, depth: usize) -> Option<&AncestorFactory<V>> {
+        self.factories.get(depth).and_then(Option::as_ref).map(|(_, factory)| factory)
+    }
+
+    /// Check if the ancestors at the depth are created with the bundle `B`.
+    pub fn is_bundle<B>(&self, depth: usize) -> bool
+    where
+        B: Bundle,
+    {
+        self.factories.get(depth).and_then(Option::as_ref).is_some_and(|(type_id, _)| *type_id == TypeId::of::<B>())
+    }
+}
+
+/// Insert a `BiologicalClock` on an ancestor of the bundle `B`, unless the `ClockInsertion` of the bundle is disabled.
+fn insert_biological_clock<B>(mut entity: EntityWorldMut)
+where
+    B: Bundle,
+{
+    let ages = entity.world().get_resource::<ClockInsertion<B>>().is_none_or(|clock_insertion| clock_insertion.enabled);
+    if ages && !entity.contains::<BiologicalClock>() {
+        entity.insert(BiologicalClock::default());
+    }
+}
+
+/// Ancestors that are spawned but not applied to the world yet.
+pub struct SpawnedAncestors<V> {
+    spawned: Vec<(FamilyPath<V>, Entity)>,
+}
+
+impl<V> Default for SpawnedAncestors<V> {
+    fn default() -> Self {
+        Self { spawned: Vec::new() }
+    }
+}
+
+/// System parameter that creates the missing ancestors of an entity, by its path
+#[derive(SystemParam)]
+pub struct Ancestors<'w, 's, V>
+where
+    V: Send + Sync + 'static,
+{
+    bundles: Option<Res<'w, AncestorBundles<V>>>,
+    spawned: Local<'s, SpawnedAncestors<V>>,
+}
+
+impl<V> Ancestors<'_, '_, V>
+where
+    V: Clone + std::fmt::Debug + PartialEq + Send + Sync + 'static,
+{
+    /// Start counting the ancestors of a new run of the system.
+    pub fn begin(&mut self) {
+        self.spawned.spawned.clear();
+    }
+
+    /// Find the deepest entity on the path that exists or is spawned, with the number of identifiers up to it.
+    pub fn find(&self, path: &FamilyPath<V>, family: &FamilyQuery<V>) -> (Option<Entity>, usize) {
+        let identifiers = path.identifiers();
+        (1..=identifiers.len())
+            .rev()
+            .find_map(|len| self.get(&identifiers[..len], family).map(|entity| (Some(entity), len)))
+            .unwrap_or((None, 0))
+    }
+
+    /// Tell which of the ancestors after the existing ones are created with the bundle `U`.
+    pub fn members<U>(&self, path: &FamilyPath<V>, existing: usize) -> Vec<bool>
+    where
+        U: Bundle,
+    {
+        (existing..path.identifiers().len()).map(|depth| self.bundles.as_ref().is_some_and(|bundles| bundles.is_bundle::<U>(depth))).collect()
+    }
+
+    /// Spawn the ancestors on the path after the existing ones, under the deepest existing one, and get the entity at the path.
+    ///
+    /// The ancestors should have been checked with `Population::make_room_for_ancestors`. Every spawned entity is counted in the population
+    /// and recorded in the lineage as created.
+    #[allow(clippy::too_many_arguments)]
+    pub fn spawn<U>(
+        &mut self,
+        path: &FamilyPath<V>,
+        (mut parent, existing): (Option<Entity>, usize),
+        ages: bool,
+        population: &mut Population<U, V>,
+        commands: &mut Commands,
+        lineage: &mut Lineage<V>,
+        generations: &mut Generations<V>,
+    ) -> Entity
+    where
+        U: Bundle,
+    {
+        let identifiers = path.identifiers();
+        for depth in existing..identifiers.len() {
+            let self_identifier = &identifiers[depth];
+            let parent_identifier = depth.checked_sub(1).map(|index| identifiers[index].clone());
+            let generation = generations.next(&parent_identifier, self_identifier);
+            let mut entity_commands = commands.spawn((self_identifier.clone(), Generation(generation)));
+            match self.bundles.as_ref().and_then(|bundles| bundles.get_factory(depth)) {
+                Some(factory) => factory(&mut entity_commands, self_identifier),
+                None if ages => {
+                    entity_commands.insert(BiologicalClock::default());
+                }
+                None => {}
+            }
+            let entity = entity_commands.id();
+            if let Some(parent) = parent {
+                commands.entity(parent).add_child(entity);
+            }
+            commands.queue(move |world: &mut World| capture_ancestor::<V>(world, entity));
+            population.count_ancestor(parent, entity, self.bundles.as_ref().is_some_and(|bundles| bundles.is_bundle::<U>(depth)));
+            debug!("Missing ancestor {:?} of {:?} created.", self_identifier, path.leaf());
+            lineage.add_history(History::new(Action::Create, parent_identifier, self_identifier.clone(), Ok(())).with_generation(generation));
+            if let Some(ancestor_path) = FamilyPath::from_identifiers(identifiers[..=depth].to_vec()) {
+                self.spawned.spawned.push((ancestor_path, entity));
+            }
+            parent = Some(entity);
+        }
+        parent.expect("a path always has a root")
+    }
+
+    /// Get the entity at the path from the identifiers, if it exists or is spawned.
+    fn get(&self, identifiers: &[Identifier<V>], family: &FamilyQuery<V>) -> Option<Entity> {
+        let path = FamilyPath::from_identifiers(identifiers.to_vec())?;
+        self.spawned
+            .spawned
+            .iter()
+            .find(|(spawned_path, _)| *spawned_path == path)
+            .map(|(_, entity)| *entity)
+            .or_else(|| family.get_by_path(&path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{app, Node};
+    use crate::{CudEvent, FamilyConstraints, FamilyError, PopulationCap};
+    use bevy_app::prelude::*;
+
+    fn create(app: &mut App, paths: &[&str]) {
+        for path in paths {
+            app.world_mut().send_event(CudEvent::<Node, String>::create_with_ancestors(path.parse().unwrap(), Node::default()));
+        }
+        app.update();
+    }
+
+    fn members(app: &mut App) -> usize {
+        app.world_mut().query::<&Identifier<String>>().iter(app.world()).count()
+    }
+
+    #[test]
+    fn creates_missing_ancestors() {
+        let mut app = app();
+        create(&mut app, &["R/a/b/c", "R/a/d"]);
+        assert_eq!(members(&mut app), 5);
+        assert!(app.world().resource::<Lineage<String>>().histories.iter().all(|history| history.result.is_ok()));
+    }
+
+    #[test]
+    fn rejects_the_whole_chain_over_the_constraints() {
+        let mut app = app();
+        app.insert_resource(FamilyConstraints::<String>::default().with_max_members_per_root(3));
+        create(&mut app, &["R/a/b/c"]);
+        assert_eq!(members(&mut app), 0);
+        assert_eq!(app.world().resource::<Lineage<String>>().histories.last().unwrap().result, Err(FamilyError::MaxMembersExceeded));
+
+        create(&mut app, &["R/a/b", "R/a/c"]);
+        assert_eq!(members(&mut app), 3);
+        assert_eq!(app.world().resource::<Lineage<String>>().histories.last().unwrap().result, Err(FamilyError::MaxMembersExceeded));
+    }
+
+    #[test]
+    fn counts_ancestors_of_the_bundle_in_the_population() {
+        let mut app = app();
+        app.insert_resource(AncestorBundles::<String>::default().with_bundle(0, Node::default()).with_bundle(1, Node::default()));
+        app.insert_resource(PopulationCap::<Node>::default().with_per_type(2));
+        create(&mut app, &["R/a/b/c"]);
+        assert_eq!(members(&mut app), 0);
+        assert_eq!(app.world().resource::<Lineage<String>>().histories.last().unwrap().result, Err(FamilyError::PopulationCapReached));
+
+        create(&mut app, &["R/a"]);
+        assert_eq!(members(&mut app), 2);
+        assert_eq!(app.world_mut().query::<(&Node, &BiologicalClock)>().iter(app.world()).count(), 2);
+    }
+}
@@ -0,0 +1,421 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+use bevy_app::prelude::*;
+use bevy_asset::io::Reader;
+use bevy_asset::prelude::*;
+use bevy_asset::{AssetLoader, LoadContext};
+use bevy_ecs::prelude::*;
+use bevy_ecs::reflect::AppTypeRegistry;
+use bevy_log::prelude::*;
+use bevy_reflect::serde::TypedReflectDeserializer;
+use bevy_reflect::{FromReflect, GetTypeRegistration, PartialReflect, TypePath, TypeRegistry};
+use serde::de::{DeserializeOwned, DeserializeSeed};
+use serde::Deserialize;
+
+use std::any::TypeId;
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::marker::PhantomData;
+
+use crate::{CudEvent, FamilyPath};
+
+/// A node of a `.family.ron` file, as it is written.
+#[derive(Deserialize)]
+#[serde(bound(deserialize = "V: Deserialize<'de>"))]
+struct FamilyFileNode<V> {
+    identifier: V,
+    bundle: String,
+    #[serde(default)]
+    data: Option<ron::Value>,
+    #[serde(default)]
+    children: Vec<FamilyFileNode<V>>,
+}
+
+/// The contents of a `.family.ron` file, as it is written.
+#[derive(Deserialize)]
+#[serde(bound(deserialize = "V: Deserialize<'de>"))]
+struct FamilyFile<V> {
+    roots: Vec<FamilyFileNode<V>>,
+}
+
+/// A node of a `FamilyAsset`, with its bundle data read through reflection.
+#[derive(TypePath)]
+pub struct FamilyAssetNode<V> {
+    pub identifier: V,
+    /// Type path of the bundle, as it is written in the file.
+    pub bundle: String,
+    pub bundle_type_id: TypeId,
+    /// Data of the bundle, or `None` to create its default.
+    pub data: Option<Box<dyn PartialReflect>>,
+    pub children: Vec<FamilyAssetNode<V>>,
+}
+
+/// Family trees that are loaded from a `.family.ron` file
+///
+/// ```ron
+/// (
+///     roots: [
+///         (
+///             identifier: "Building",
+///             bundle: "Building",
+///             data: Some((floors: 2)),
+///             children: [
+///                 (identifier: "Level 1", bundle: "Level"),
+///             ],
+///         ),
+///     ],
+/// )
+/// ```
+///
+/// The bundles are found in the type registry by their type path or short type path, and their data is read through reflection.
+#[derive(Asset, TypePath)]
+pub struct FamilyAsset<V>
+where
+    V: TypePath + Send + Sync,
+{
+    pub roots: Vec<FamilyAssetNode<V>>,
+}
+
+impl<V> FamilyAsset<V>
+where
+    V: TypePath + DeserializeOwned + Send + Sync,
+{
+    /// Read the family trees from the RON text, with the bundles of the type registry.
+    pub fn from_ron(bytes: &[u8], type_registry: &TypeRegistry) -> Result<Self, FamilyAssetError> {
+        let file: FamilyFile<V> = ron::de::from_bytes(bytes).map_err(|error| FamilyAssetError::Ron(error.to_string()))?;
+        let roots = file.roots.into_iter().map(|node| Self::read_node(node, type_registry)).collect::<Result<_, _>>()?;
+        Ok(Self { roots })
+    }
+
+    /// Read the bundle data of the node and its children
+    fn read_node(node: FamilyFileNode<V>, type_registry: &TypeRegistry) -> Result<FamilyAssetNode<V>, FamilyAssetError> {
+        let registration = type_registry
+            .get_with_type_path(&node.bundle)
+            .or_else(|| type_registry.get_with_short_type_path(&node.bundle))
+            .ok_or_else(|| FamilyAssetError::UnknownBundle(node.bundle.clone()))?;
+        let data = match node.data {
+            Some(data) => Some(
+                TypedReflectDeserializer::new(registration, type_registry)
+                    .deserialize(data)
+                    .map_err(|error| FamilyAssetError::InvalidData(node.bundle.clone(), error.to_string()))?,
+            ),
+            None => None,
+        };
+        Ok(FamilyAssetNode {
+            identifier: node.identifier,
+            bundle_type_id: registration.type_id(),
+            bundle: node.bundle,
+            data,
+            children: node.children.into_iter().map(|child| Self::read_node(child, type_registry)).collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/// Reason that a `.family.ron` file cannot be loaded.
+#[derive(Debug)]
+pub enum FamilyAssetError {
+    Io(std::io::Error),
+    /// The file is not valid RON.
+    Ron(String),
+    /// The bundle is not in the type registry.
+    UnknownBundle(String),
+    /// The data cannot be read into the bundle.
+    InvalidData(String, String),
+}
+
+impl Display for FamilyAssetError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FamilyAssetError::Io(error) => write!(f, "cannot read family file: {}", error),
+            FamilyAssetError::Ron(error) => write!(f, "invalid family file: {}", error),
+            FamilyAssetError::UnknownBundle(bundle) => write!(f, "bundle {} is not registered", bundle),
+            FamilyAssetError::InvalidData(bundle, error) => write!(f, "invalid data for bundle {}: {}", bundle, error),
+        }
+    }
+}
+
+impl std::error::Error for FamilyAssetError {}
+
+/// Loads `.family.ron` files into `FamilyAsset`s.
+pub struct FamilyAssetLoader<V> {
+    type_registry: AppTypeRegistry,
+    _marker: PhantomData<V>,
+}
+
+impl<V> FromWorld for FamilyAssetLoader<V> {
+    fn from_world(world: &mut World) -> Self {
+        Self {
+            type_registry: world.resource::<AppTypeRegistry>().clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<V> AssetLoader for FamilyAssetLoader<V>
+where
+    V: TypePath + DeserializeOwned + Send + Sync + 'static,
+{
+    type Asset = FamilyAsset<V>;
+    type Settings = ();
+    type Error = FamilyAssetError;
+
+    async fn load(&self, reader: &mut dyn Reader, _settings: &(), _load_context: &mut LoadContext<'_>) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await.map_err(FamilyAssetError::Io)?;
+        FamilyAsset::from_ron(&bytes, &self.type_registry.read())
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["family.ron"]
+    }
+}
+
+/// Function that sends the `CudEvent` that creates a node of a `FamilyAsset` with its bundle.
+pub type AssetNodeSender<V> = fn(&mut Commands, FamilyPath<V>, Option<&dyn PartialReflect>) -> Result<(), String>;
+
+/// Resource with the senders of the bundles that can be created from a `FamilyAsset`, by their type.
+#[derive(Resource)]
+pub struct FamilyAssetBundles<V> {
+    senders: Vec<(TypeId, AssetNodeSender<V>)>,
+}
+
+impl<V> Default for FamilyAssetBundles<V> {
+    fn default() -> Self {
+        Self { senders: Vec::new() }
+    }
+}
+
+impl<V> FamilyAssetBundles<V> {
+    /// Get the sender of the bundle by its type.
+    pub fn get(&self, type_id: TypeId) -> Option<AssetNodeSender<V>> {
+        self.senders.iter().find(|(sender_type_id, _)| *sender_type_id == type_id).map(|(_, sender)| *sender)
+    }
+}
+
+/// Event that creates the family trees of a `FamilyAsset`, under the parent if there is one
+#[derive(Event)]
+pub struct SpawnFamilyAsset<V>
+where
+    V: TypePath + Send + Sync,
+{
+    pub handle: Handle<FamilyAsset<V>>,
+    pub parent_path: Option<FamilyPath<V>>,
+}
+
+/// A node of a `FamilyAsset` that is waiting to be created.
+struct PendingNode<V> {
+    path: FamilyPath<V>,
+    bundle: String,
+    bundle_type_id: TypeId,
+    data: Option<Box<dyn PartialReflect>>,
+}
+
+/// Family assets that are waiting to be loaded, and the nodes of the loaded ones that are waiting for their parents, level by level.
+pub struct PendingFamilyAssets<V>
+where
+    V: TypePath + Send + Sync,
+{
+    loading: Vec<SpawnFamilyAsset<V>>,
+    levels: Vec<VecDeque<Vec<PendingNode<V>>>>,
+}
+
+impl<V> Default for PendingFamilyAssets<V>
+where
+    V: TypePath + Send + Sync,
+{
+    fn default() -> Self {
+        Self {
+            loading: Vec::new(),
+            levels: Vec::new(),
+        }
+    }
+}
+
+/// Plugin that creates the bundle `U` from the nodes of `.family.ron` files with the identifier `V`
+///
+/// The nodes are created through `cud_bundle::<U, V>`, so they are validated and recorded in the `Lineage` like any other creation.
+/// Add one plugin for every bundle that is used in the files.
+pub struct FamilyAssetPlugin<U, V> {
+    _marker: PhantomData<(U, V)>,
+}
+
+impl<U, V> Default for FamilyAssetPlugin<U, V> {
+    fn default() -> Self {
+        Self { _marker: PhantomData }
+    }
+}
+
+impl<U, V> Plugin for FamilyAssetPlugin<U, V>
+where
+    U: Bundle + Clone + Default + FromReflect + GetTypeRegistration,
+    V: Clone + std::fmt::Debug + PartialEq + TypePath + DeserializeOwned + Send + Sync + 'static,
+{
+    /// Build the plugin
+    fn build(&self, app: &mut App) {
+        app.register_type::<U>();
+        if !app.world().contains_resource::<FamilyAssetBundles<V>>() {
+            app.init_resource::<FamilyAssetBundles<V>>();
+            app.init_asset::<FamilyAsset<V>>();
+            app.init_asset_loader::<FamilyAssetLoader<V>>();
+            app.add_event::<SpawnFamilyAsset<V>>();
+            app.add_systems(PreUpdate, spawn_family_assets::<V>);
+        }
+        app.world_mut().resource_mut::<FamilyAssetBundles<V>>().senders.push((TypeId::of::<U>(), send_asset_node::<U, V>));
+    }
+}
+
+/// Send the `CudEvent` that creates the node with its bundle
+fn send_asset_node<U, V>(commands: &mut Commands, path: FamilyPath<V>, data: Option<&dyn PartialReflect>) -> Result<(), String>
+where
+    U: Bundle + Clone + Default + FromReflect,
+    V: Clone + Send + Sync + 'static,
+{
+    let bundle = match data {
+        Some(data) => U::from_reflect(data).ok_or_else(|| format!("data does not match {}", std::any::type_name::<U>()))?,
+        None => U::default(),
+    };
+    commands.send_event(CudEvent::<U, V>::create_at_path(path, bundle));
+    Ok(())
+}
+
+/// Creates the nodes of the loaded family assets, one level every frame so that the parents exist before their children
+pub fn spawn_family_assets<V>(
+    mut commands: Commands,
+    mut events: EventReader<SpawnFamilyAsset<V>>,
+    assets: Res<Assets<FamilyAsset<V>>>,
+    asset_server: Res<AssetServer>,
+    bundles: Res<FamilyAssetBundles<V>>,
+    mut pending: Local<PendingFamilyAssets<V>>,
+) where
+    V: Clone + std::fmt::Debug + TypePath + Send + Sync + 'static,
+{
+    pending.loading.extend(events.read().map(|event| SpawnFamilyAsset {
+        handle: event.handle.clone(),
+        parent_path: event.parent_path.clone(),
+    }));
+
+    let mut loading = std::mem::take(&mut pending.loading);
+    loading.retain(|spawn| {
+        if let Some(asset) = assets.get(&spawn.handle) {
+            let mut levels = VecDeque::new();
+            for root in &asset.roots {
+                let path = match &spawn.parent_path {
+                    Some(parent_path) => parent_path.clone().join(root.identifier.clone()),
+                    None => FamilyPath::root(root.identifier.clone()),
+                };
+                flatten_node(root, path, 0, &mut levels);
+            }
+            pending.levels.push(levels);
+            false
+        } else if asset_server.load_state(&spawn.handle).is_failed() {
+            warn!("Family asset {:?} cannot be loaded.", spawn.handle.path());
+            false
+        } else {
+            true
+        }
+    });
+    pending.loading = loading;
+
+    for levels in pending.levels.iter_mut() {
+        for node in levels.pop_front().into_iter().flatten() {
+            let sent = match bundles.get(node.bundle_type_id) {
+                Some(sender) => sender(&mut commands, node.path.clone(), node.data.as_deref()),
+                None => Err(format!("no FamilyAssetPlugin for bundle {}", node.bundle)),
+            };
+            if let Err(error) = sent {
+                warn!("Entity {:?} of a family asset cannot be created: {}.", node.path.leaf(), error);
+            }
+        }
+    }
+    pending.levels.retain(|levels| !levels.is_empty());
+}
+
+/// Add the node and its descendants to the levels of their depth
+fn flatten_node<V>(node: &FamilyAssetNode<V>, path: FamilyPath<V>, depth: usize, levels: &mut VecDeque<Vec<PendingNode<V>>>)
+where
+    V: Clone,
+{
+    if levels.len() <= depth {
+        levels.resize_with(depth + 1, Vec::new);
+    }
+    for child in &node.children {
+        flatten_node(child, path.clone().join(child.identifier.clone()), depth + 1, levels);
+    }
+    levels[depth].push(PendingNode {
+        path,
+        bundle: node.bundle.clone(),
+        bundle_type_id: node.bundle_type_id,
+        data: node.data.as_ref().map(|data| data.clone_value()),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy_reflect::Reflect;
+
+    #[derive(Component, Reflect, Clone, Debug, Default, PartialEq)]
+    struct Building {
+        floors: u32,
+    }
+
+    #[derive(Component, Reflect, Clone, Default)]
+    struct Level;
+
+    fn type_registry() -> TypeRegistry {
+        let mut type_registry = TypeRegistry::default();
+        type_registry.register::<Building>();
+        type_registry.register::<Level>();
+        type_registry
+    }
+
+    #[test]
+    fn reads_the_bundles_from_the_registry() {
+        let ron = r#"(roots: [(identifier: "B", bundle: "Building", data: Some((floors: 2)), children: [(identifier: "L", bundle: "Level")])])"#;
+        let asset = FamilyAsset::<String>::from_ron(ron.as_bytes(), &type_registry()).unwrap();
+        let root = &asset.roots[0];
+        assert_eq!((root.identifier.as_str(), root.bundle_type_id), ("B", TypeId::of::<Building>()));
+        assert_eq!(root.data.as_deref().and_then(Building::from_reflect), Some(Building { floors: 2 }));
+
+        let child = &root.children[0];
+        assert_eq!((child.identifier.as_str(), child.bundle_type_id), ("L", TypeId::of::<Level>()));
+        assert!(child.data.is_none());
+
+        // a node without data is created with the default bundle
+        let mut world = World::new();
+        world.init_resource::<Events<CudEvent<Building, String>>>();
+        send_asset_node::<Building, String>(&mut world.commands(), FamilyPath::root("B".to_string()), root.data.as_deref()).unwrap();
+        send_asset_node::<Building, String>(&mut world.commands(), FamilyPath::root("C".to_string()), None).unwrap();
+        world.flush();
+        let events = world.resource::<Events<CudEvent<Building, String>>>();
+        let bundles: Vec<Building> = events.iter_current_update_events().map(|event| event.get_bundle_ref().clone()).collect();
+        assert_eq!(bundles, [Building { floors: 2 }, Building::default()]);
+    }
+
+    #[test]
+    fn fails_on_an_unknown_bundle_or_invalid_data() {
+        let ron = r#"(roots: [(identifier: "B", bundle: "Building", children: [(identifier: "L", bundle: "Basement")])])"#;
+        let error = FamilyAsset::<String>::from_ron(ron.as_bytes(), &type_registry()).err();
+        assert!(matches!(error, Some(FamilyAssetError::UnknownBundle(bundle)) if bundle == "Basement"));
+
+        let ron = r#"(roots: [(identifier: "B", bundle: "Building", data: Some((floors: "two")))])"#;
+        let error = FamilyAsset::<String>::from_ron(ron.as_bytes(), &type_registry()).err();
+        assert!(matches!(error, Some(FamilyAssetError::InvalidData(bundle, _)) if bundle == "Building"));
+    }
+}
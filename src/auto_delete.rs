@@ -0,0 +1,252 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
+use bevy_hierarchy::prelude::*;
+use bevy_log::prelude::*;
+
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+use crate::{Action, FamilyMember, Generation, History, Identifier, Lineage};
+
+/// Resource that holds the rule to delete the parents of the bundle `U` once they lose their children.
+#[derive(Resource)]
+pub struct AutoDelete<U> {
+    /// Parents are deleted once they have fewer children than this.
+    pub min_children: usize,
+    /// Also delete the ancestors of the bundle that fall below the minimum by the deletion, all the way up.
+    pub recursive: bool,
+    _marker: PhantomData<U>,
+}
+
+impl<U> Default for AutoDelete<U> {
+    fn default() -> Self {
+        Self {
+            min_children: 1,
+            recursive: false,
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// Plugin that deletes the parents of the bundle `U` once their last child is removed.
+///
+/// Only parents that lose children are deleted, so a parent that never had children is kept.
+pub struct AutoDeletePlugin<U, V> {
+    min_children: usize,
+    recursive: bool,
+    _marker: PhantomData<(U, V)>,
+}
+
+impl<U, V> Default for AutoDeletePlugin<U, V> {
+    fn default() -> Self {
+        Self {
+            min_children: 1,
+            recursive: false,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<U, V> AutoDeletePlugin<U, V> {
+    /// Delete the parents once they have fewer children than the minimum.
+    pub fn with_min_children(mut self, min_children: usize) -> Self {
+        self.min_children = min_children;
+        self
+    }
+
+    /// Also delete the ancestors of the bundle that fall below the minimum, all the way up.
+    pub fn recursive(mut self) -> Self {
+        self.recursive = true;
+        self
+    }
+}
+
+impl<U, V> Plugin for AutoDeletePlugin<U, V>
+where
+    U: Bundle,
+    V: Clone + std::fmt::Debug + PartialEq + Send + Sync + 'static,
+{
+    /// Build the plugin
+    fn build(&self, app: &mut App) {
+        app.insert_resource(AutoDelete::<U> {
+            min_children: self.min_children,
+            recursive: self.recursive,
+            _marker: PhantomData,
+        });
+        app.add_systems(PostUpdate, delete_childless_parents::<U, V>);
+    }
+}
+
+/// Filter for the members of the bundle `U` whose children have changed.
+type ChangedMembers<U> = (With<FamilyMember<U>>, Changed<Children>);
+
+/// Data of an entity that is needed to walk up the family tree, and if it is a member of the bundle `U`.
+type FamilyData<U, V> = (Option<&'static Identifier<V>>, Option<&'static Parent>, Option<&'static Children>, Has<FamilyMember<U>>, Option<&'static Generation>);
+
+/// Deletes the parents of the bundle `U` that have lost children and fell below the minimum number of children
+pub fn delete_childless_parents<U, V>(
+    mut commands: Commands,
+    rule: Res<AutoDelete<U>>,
+    changed_queries: Query<(Entity, &Children), ChangedMembers<U>>,
+    family_queries: Query<FamilyData<U, V>>,
+    mut removed_children: RemovedComponents<Children>,
+    mut child_counts: Local<HashMap<Entity, usize>>,
+    mut lineage: ResMut<Lineage<V>>,
+) where
+    U: Bundle,
+    V: Clone + std::fmt::Debug + PartialEq + Send + Sync + 'static,
+{
+    let mut childless = Vec::new();
+    for (entity, children) in changed_queries.iter() {
+        let previous = child_counts.insert(entity, children.len()).unwrap_or(0);
+        if children.len() < previous && children.len() < rule.min_children {
+            childless.push(entity);
+        }
+    }
+    for entity in removed_children.read() {
+        // the parent is gone, or it only lost its children
+        if let Some(previous) = child_counts.remove(&entity) {
+            if previous > 0 && family_queries.get(entity).is_ok_and(|(_, _, _, is_member, _)| is_member) {
+                childless.push(entity);
+            }
+        }
+    }
+
+    let mut deleted = Vec::new();
+    for entity in childless {
+        let mut current = Some(entity);
+        while let Some(entity) = current.take() {
+            if deleted.contains(&entity) {
+                break;
+            }
+            let Ok((self_identifier, parent, _, _, generation)) = family_queries.get(entity) else {
+                break;
+            };
+            commands.entity(entity).despawn_recursive();
+            deleted.push(entity);
+            child_counts.remove(&entity);
+
+            let parent_identifier = parent.and_then(|parent| family_queries.get(parent.get()).ok()).and_then(|(identifier, _, _, _, _)| identifier).cloned();
+            if let Some(self_identifier) = self_identifier {
+                debug!("Entity {:?} is deleted as it has lost its children.", self_identifier);
+                lineage.add_history(History::new(Action::Delete, parent_identifier, self_identifier.clone(), Ok(())).with_generation(generation.map_or(0, |generation| generation.0)));
+            }
+
+            // check if the parent falls below the minimum by this deletion
+            if !rule.recursive {
+                continue;
+            }
+            let Some(parent) = parent.map(|parent| parent.get()) else {
+                continue;
+            };
+            // only the parents of the bundle follow its rule
+            if !family_queries.get(parent).is_ok_and(|(_, _, _, is_member, _)| is_member) {
+                continue;
+            }
+            let remaining = family_queries.get(parent).ok().and_then(|(_, _, children, _, _)| children).map_or(0, |children| children.iter().filter(|child| !deleted.contains(child)).count());
+            if remaining < rule.min_children {
+                current = Some(parent);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{identifiers, send, Node};
+    use crate::{AncestorBundles, CudEvent};
+
+    #[derive(Component, Clone, Default)]
+    struct Building;
+
+    /// Create the app where the missing ancestors are also members of `Node`.
+    fn app() -> App {
+        let mut app = crate::testing::app();
+        app.insert_resource(AncestorBundles::<String>::default().with_bundle(0, Node::default()).with_bundle(1, Node::default()));
+        app
+    }
+
+    fn create(app: &mut App, path: &str) {
+        send(app, CudEvent::create_with_ancestors(path.parse().unwrap(), Node::default()));
+    }
+
+    fn delete(app: &mut App, parent_identifier: &str, identifier: &str) {
+        send(app, CudEvent::delete_child(parent_identifier.to_string(), identifier.to_string(), Node::default()));
+    }
+
+    #[test]
+    fn deletes_the_parent_after_its_last_child() {
+        let mut app = app();
+        app.add_plugins(AutoDeletePlugin::<Node, String>::default());
+        create(&mut app, "P/A");
+        create(&mut app, "P/B");
+
+        delete(&mut app, "P", "A");
+        assert_eq!(identifiers(&mut app), ["B", "P"]);
+        delete(&mut app, "P", "B");
+        assert_eq!(identifiers(&mut app), [""; 0]);
+        let history = app.world().resource::<Lineage<String>>().histories.last().unwrap();
+        assert_eq!((&history.action, &history.parent_identifier.0), (&Action::Delete, &"P".to_string()));
+    }
+
+    #[test]
+    fn deletes_the_parent_below_the_minimum() {
+        let mut app = app();
+        app.add_plugins(AutoDeletePlugin::<Node, String>::default().with_min_children(2));
+        for path in ["P/A", "P/B", "P/C"] {
+            create(&mut app, path);
+        }
+
+        delete(&mut app, "P", "A");
+        assert_eq!(identifiers(&mut app), ["B", "C", "P"]);
+        delete(&mut app, "P", "B");
+        assert_eq!(identifiers(&mut app), [""; 0]);
+    }
+
+    #[test]
+    fn deletes_the_ancestors_of_the_bundle_recursively() {
+        let mut app = app();
+        app.add_plugins(AutoDeletePlugin::<Node, String>::default().recursive());
+        app.add_event::<CudEvent<Building, String>>();
+        app.add_systems(Update, crate::cud_bundle::<Building, String>);
+        create(&mut app, "R/P/A");
+        app.world_mut().send_event(CudEvent::create_parent("Building".to_string(), Building));
+        app.update();
+        send(&mut app, CudEvent::create_child("Building".to_string(), "Level".to_string(), Node::default()));
+        send(&mut app, CudEvent::create_child("Level".to_string(), "Room".to_string(), Node::default()));
+
+        delete(&mut app, "P", "A");
+        assert_eq!(identifiers(&mut app), ["Building", "Level", "Room"]);
+        delete(&mut app, "Level", "Room");
+        assert_eq!(identifiers(&mut app), ["Building"]);
+    }
+
+    #[test]
+    fn keeps_a_parent_that_never_had_children() {
+        let mut app = app();
+        app.add_plugins(AutoDeletePlugin::<Node, String>::default().recursive());
+        create(&mut app, "P");
+        create(&mut app, "R/A");
+        app.update();
+        assert_eq!(identifiers(&mut app), ["A", "P", "R"]);
+    }
+}
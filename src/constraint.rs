@@ -0,0 +1,237 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+use bevy_ecs::prelude::*;
+use bevy_ecs::system::SystemParam;
+use bevy_hierarchy::prelude::*;
+
+use std::marker::PhantomData;
+
+use crate::{FamilyDepth, FamilyError, Identifier};
+
+/// Resource that limits the shape of the family trees with the identifier `T`.
+#[derive(Resource)]
+pub struct FamilyConstraints<T> {
+    pub max_depth: Option<usize>,
+    pub max_children: Option<usize>,
+    pub max_members_per_root: Option<usize>,
+    _marker: PhantomData<T>,
+}
+
+impl<T> Default for FamilyConstraints<T> {
+    fn default() -> Self {
+        Self {
+            max_depth: None,
+            max_children: None,
+            max_members_per_root: None,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> FamilyConstraints<T> {
+    /// Limit the `FamilyDepth` of the entities, roots are at depth 0.
+    pub fn with_max_depth(mut self, max: usize) -> Self {
+        self.max_depth = Some(max);
+        self
+    }
+
+    /// Limit the number of children under each parent.
+    pub fn with_max_children(mut self, max: usize) -> Self {
+        self.max_children = Some(max);
+        self
+    }
+
+    /// Limit the number of members in each tree, the root included.
+    pub fn with_max_members_per_root(mut self, max: usize) -> Self {
+        self.max_members_per_root = Some(max);
+        self
+    }
+}
+
+/// Entities that are validated but not spawned yet, as their parent and root, and the missing ancestors that are spawned, with their root and depth.
+#[derive(Default)]
+pub struct PendingMembers {
+    spawned: Vec<(Entity, Entity)>,
+    ancestors: Vec<(Entity, Entity, usize)>,
+}
+
+/// System parameter that enforces the `FamilyConstraints` of the identifier `T` before entities are spawned
+#[derive(SystemParam)]
+pub struct Constraints<'w, 's, T>
+where
+    T: Send + Sync + 'static,
+{
+    constraints: Option<Res<'w, FamilyConstraints<T>>>,
+    members: Query<'w, 's, (), With<Identifier<T>>>,
+    parents: Query<'w, 's, &'static Parent>,
+    children: Query<'w, 's, &'static Children>,
+    depths: Query<'w, 's, &'static FamilyDepth>,
+    pending: Local<'s, PendingMembers>,
+}
+
+impl<T> Constraints<'_, '_, T>
+where
+    T: Send + Sync + 'static,
+{
+    /// Start counting the entities of a new run of the system.
+    pub fn begin(&mut self) {
+        self.pending.spawned.clear();
+        self.pending.ancestors.clear();
+    }
+
+    /// Check that a new entity can be spawned under the parent, without counting it.
+    pub fn check(&self, parent: Option<Entity>) -> Result<(), FamilyError> {
+        let (Some(constraints), Some(parent)) = (&self.constraints, parent) else {
+            return Ok(());
+        };
+        self.check_depth(self.depth_of(parent) + 1)?;
+        if let Some(max) = constraints.max_children {
+            if self.count_children(parent) >= max {
+                return Err(FamilyError::MaxChildrenExceeded);
+            }
+        }
+        if let Some(max) = constraints.max_members_per_root {
+            if self.count_members(self.root_of(parent)) >= max {
+                return Err(FamilyError::MaxMembersExceeded);
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that a chain of new entities can be spawned under the parent, each one under the previous one, without counting them.
+    pub fn check_chain(&self, parent: Option<Entity>, len: usize) -> Result<(), FamilyError> {
+        let Some(constraints) = &self.constraints else {
+            return Ok(());
+        };
+        if len == 0 {
+            return Ok(());
+        }
+        let depth = parent.map_or(0, |parent| self.depth_of(parent) + 1);
+        self.check_depth(depth + len - 1)?;
+        if let Some(max) = constraints.max_children {
+            // the entities after the first one are the only child of a new parent
+            let first = parent.map_or(0, |parent| self.count_children(parent));
+            if first >= max || (len > 1 && max == 0) {
+                return Err(FamilyError::MaxChildrenExceeded);
+            }
+        }
+        if let Some(max) = constraints.max_members_per_root {
+            let members = parent.map_or(0, |parent| self.count_members(self.root_of(parent)));
+            if members + len > max {
+                return Err(FamilyError::MaxMembersExceeded);
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that a new entity can be spawned at the depth.
+    pub fn check_depth(&self, depth: usize) -> Result<(), FamilyError> {
+        match self.constraints.as_ref().and_then(|constraints| constraints.max_depth) {
+            Some(max) if depth > max => Err(FamilyError::MaxDepthExceeded),
+            _ => Ok(()),
+        }
+    }
+
+    /// Count a new entity under the parent as spawned.
+    pub fn count(&mut self, parent: Option<Entity>) {
+        if let Some(parent) = parent {
+            let root = self.root_of(parent);
+            self.pending.spawned.push((parent, root));
+        }
+    }
+
+    /// Count a missing ancestor that is spawned under the parent, so that the entities spawned under it find its depth and root.
+    pub fn count_ancestor(&mut self, parent: Option<Entity>, entity: Entity) {
+        let (root, depth) = match parent {
+            Some(parent) => (self.root_of(parent), self.depth_of(parent) + 1),
+            None => (entity, 0),
+        };
+        self.count(parent);
+        self.pending.ancestors.push((entity, root, depth));
+    }
+
+    /// Count the children of the entity, the pending ones included.
+    fn count_children(&self, parent: Entity) -> usize {
+        let children = self.children.get(parent).map_or(0, |children| children.iter().filter(|child| self.members.contains(**child)).count());
+        children + self.pending.spawned.iter().filter(|(pending_parent, _)| *pending_parent == parent).count()
+    }
+
+    /// Count the members of the tree of the root, the pending ones included.
+    fn count_members(&self, root: Entity) -> usize {
+        let members = 1 + self.children.iter_descendants(root).filter(|descendant| self.members.contains(*descendant)).count();
+        members + self.pending.spawned.iter().filter(|(_, pending_root)| *pending_root == root).count()
+    }
+
+    /// Find the depth of the entity, which may be a pending ancestor.
+    fn depth_of(&self, entity: Entity) -> usize {
+        match self.pending.ancestors.iter().find(|(ancestor, _, _)| *ancestor == entity) {
+            Some((_, _, depth)) => *depth,
+            None => self.depths.get(entity).map_or_else(|_| self.parents.iter_ancestors(entity).count(), |depth| depth.0),
+        }
+    }
+
+    /// Find the root of the tree of the entity, which may be a pending ancestor.
+    fn root_of(&self, entity: Entity) -> Entity {
+        match self.pending.ancestors.iter().find(|(ancestor, _, _)| *ancestor == entity) {
+            Some((_, root, _)) => *root,
+            None => self.parents.iter_ancestors(entity).last().unwrap_or(entity),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{app, identifiers, send, Node};
+    use crate::{CudEvent, Lineage};
+    use bevy_app::prelude::*;
+
+    /// Get the results of the histories, in order.
+    fn results(app: &App) -> Vec<Result<(), FamilyError>> {
+        app.world().resource::<Lineage<String>>().histories.iter().map(|history| history.result).collect()
+    }
+
+    #[test]
+    fn rejects_entities_below_the_max_depth() {
+        let mut app = app();
+        app.insert_resource(FamilyConstraints::<String>::default().with_max_depth(1));
+        send(&mut app, CudEvent::create_parent("P".to_string(), Node::default()));
+        send(&mut app, CudEvent::create_child("P".to_string(), "A".to_string(), Node::default()));
+        send(&mut app, CudEvent::create_child("A".to_string(), "B".to_string(), Node::default()));
+        send(&mut app, CudEvent::create_with_ancestors("X/Y/Z".parse().unwrap(), Node::default()));
+        assert_eq!(results(&app), [Ok(()), Ok(()), Err(FamilyError::MaxDepthExceeded), Err(FamilyError::MaxDepthExceeded)]);
+        assert_eq!(identifiers(&mut app), ["A", "P"]);
+    }
+
+    #[test]
+    fn counts_the_children_created_in_the_same_frame() {
+        let mut app = app();
+        app.insert_resource(FamilyConstraints::<String>::default().with_max_children(2));
+        send(&mut app, CudEvent::create_parent("P".to_string(), Node::default()));
+        for identifier in ["A", "B", "C"] {
+            app.world_mut().send_event(CudEvent::create_child("P".to_string(), identifier.to_string(), Node::default()));
+        }
+        app.update();
+        assert_eq!(results(&app), [Ok(()), Ok(()), Ok(()), Err(FamilyError::MaxChildrenExceeded)]);
+        assert_eq!(identifiers(&mut app), ["A", "B", "P"]);
+
+        send(&mut app, CudEvent::create_child("P".to_string(), "D".to_string(), Node::default()));
+        assert_eq!(results(&app).last(), Some(&Err(FamilyError::MaxChildrenExceeded)));
+    }
+}
@@ -0,0 +1,350 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+use bevy_ecs::prelude::*;
+use bevy_log::prelude::*;
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::lifetime::expire_entity;
+use crate::{BiologicalClock, BiologicalTrait, Lifespan, LifetimeClock};
+
+/// A component that holds the moment that the entity expires, when using the deadline backend.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LifetimeDeadline {
+    /// Expires once the deadline clock has passed this time.
+    At(Duration),
+    /// Expires once the deadline clock has reached this frame.
+    Frame(u64),
+}
+
+/// Time of a deadline clock, shared with the `BiologicalClock`s that age from it.
+#[derive(Debug, Default)]
+struct DeadlineTime {
+    nanos: AtomicU64,
+    frame: AtomicU64,
+}
+
+/// Point of a deadline clock that a `BiologicalClock` ages from, instead of being ticked.
+#[derive(Clone, Debug)]
+pub(crate) struct ClockAnchor {
+    time: Arc<DeadlineTime>,
+    now: Duration,
+    frame: u64,
+}
+
+impl ClockAnchor {
+    /// Get the time that has passed on the deadline clock since the anchor.
+    pub(crate) fn elapsed(&self) -> Duration {
+        Duration::from_nanos(self.time.nanos.load(Ordering::Relaxed)).saturating_sub(self.now)
+    }
+
+    /// Get the number of updates of the deadline clock since the anchor.
+    pub(crate) fn frames(&self) -> u64 {
+        self.time.frame.load(Ordering::Relaxed).saturating_sub(self.frame)
+    }
+}
+
+/// Deadlines of the entities of type `T`, kept in priority queues.
+///
+/// Entries are removed lazily: an entry only expires its entity if the entity still holds the same `LifetimeDeadline`,
+/// so renewed, paused and despawned entities do not have to be searched for in the queues.
+/// The queues are rebuilt from the entities once most of their entries are stale.
+#[derive(Resource)]
+pub struct LifetimeDeadlines<T> {
+    now: Duration,
+    frame: u64,
+    time: Arc<DeadlineTime>,
+    durations: BinaryHeap<Reverse<(Duration, Entity)>>,
+    frames: BinaryHeap<Reverse<(u64, Entity)>>,
+    _marker: PhantomData<T>,
+}
+
+impl<T> Default for LifetimeDeadlines<T> {
+    fn default() -> Self {
+        Self {
+            now: Duration::ZERO,
+            frame: 0,
+            time: Arc::default(),
+            durations: BinaryHeap::new(),
+            frames: BinaryHeap::new(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> LifetimeDeadlines<T> {
+    /// Get the time that the deadline clock has advanced.
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    /// Get the number of updates that the deadline clock has advanced.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Get the number of entries in the queues, including the stale ones.
+    pub fn len(&self) -> usize {
+        self.durations.len() + self.frames.len()
+    }
+
+    /// Check if the queues are empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Compute the deadline of a lifespan, given the clock that has already been lived.
+    ///
+    /// Returns `None` if the lifespan is not supported by the deadline backend or never ends.
+    pub fn deadline_of(&self, lifespan: Lifespan, clock: &BiologicalClock) -> Option<LifetimeDeadline> {
+        match lifespan {
+            Lifespan::Duration(duration) => self.now.checked_add(duration.saturating_sub(clock.age())).map(LifetimeDeadline::At),
+            Lifespan::Frames(frames) => self.frame.checked_add(frames.saturating_sub(clock.lived_frames())).map(LifetimeDeadline::Frame),
+            Lifespan::Ticks(_) | Lifespan::UntilCondition => None,
+        }
+    }
+
+    /// Make the clock age from the deadline clock from now on, keeping the age that it has.
+    fn anchor(&self, clock: &mut BiologicalClock) {
+        *clock = clock.frozen();
+        clock.anchor = Some(ClockAnchor {
+            time: self.time.clone(),
+            now: self.now,
+            frame: self.frame,
+        });
+    }
+
+    /// Rebuild the queues from the deadlines that the entities hold, dropping the stale entries.
+    fn rebuild(&mut self, deadlines: impl Iterator<Item = (Entity, LifetimeDeadline)>) {
+        self.durations.clear();
+        self.frames.clear();
+        for (entity, deadline) in deadlines {
+            self.push(entity, deadline);
+        }
+    }
+
+    /// Push the deadline of an entity into the queues.
+    pub fn push(&mut self, entity: Entity, deadline: LifetimeDeadline) {
+        match deadline {
+            LifetimeDeadline::At(at) => self.durations.push(Reverse((at, entity))),
+            LifetimeDeadline::Frame(frame) => self.frames.push(Reverse((frame, entity))),
+        }
+    }
+
+    /// Pop every entry that is due, then advance the deadline clock by `delta` and one frame.
+    fn advance(&mut self, delta: Duration) -> Vec<(Entity, LifetimeDeadline)> {
+        let mut due = Vec::new();
+        while let Some(Reverse((at, entity))) = self.durations.peek().copied() {
+            if at >= self.now {
+                break;
+            }
+            self.durations.pop();
+            due.push((entity, LifetimeDeadline::At(at)));
+        }
+        while let Some(Reverse((frame, entity))) = self.frames.peek().copied() {
+            if frame > self.frame {
+                break;
+            }
+            self.frames.pop();
+            due.push((entity, LifetimeDeadline::Frame(frame)));
+        }
+        self.now += delta;
+        self.frame += 1;
+        self.time.nanos.store(self.now.as_nanos().try_into().unwrap_or(u64::MAX), Ordering::Relaxed);
+        self.time.frame.store(self.frame, Ordering::Relaxed);
+        due
+    }
+}
+
+/// Filter for the entities whose clock or lifespan may have changed.
+type ClockOrLifespanChanged<T> = Or<(Changed<BiologicalClock>, Changed<T>)>;
+
+/// Schedules the deadlines of the entities that are born, renewed, paused or unpaused
+///
+/// Scheduled clocks age from the deadline clock instead of being ticked. Pausing the entity's `BiologicalClock` takes the entity
+/// out of the queues and freezes its clock at its current age, so that it continues from the same age once it is unpaused.
+pub fn schedule_lifetime_deadlines<T>(
+    mut commands: Commands,
+    mut deadlines: ResMut<LifetimeDeadlines<T>>,
+    mut queries: Query<(Entity, &mut BiologicalClock, &T, Option<&LifetimeDeadline>), ClockOrLifespanChanged<T>>,
+    scheduled_queries: Query<(Entity, &LifetimeDeadline), With<T>>,
+) where
+    T: Component + BiologicalTrait,
+{
+    if deadlines.len() > 2 * scheduled_queries.iter().len() + 64 {
+        deadlines.rebuild(scheduled_queries.iter().map(|(entity, deadline)| (entity, *deadline)));
+    }
+
+    for (entity, mut biological_clock, component, deadline) in queries.iter_mut() {
+        let lifespan = component.get_lifespan();
+        if biological_clock.lifetime.is_paused() {
+            if deadline.is_some() || biological_clock.anchor.is_some() {
+                let frozen = biological_clock.frozen();
+                *biological_clock.bypass_change_detection() = frozen;
+                commands.entity(entity).remove::<LifetimeDeadline>();
+            }
+            continue;
+        }
+        match deadlines.deadline_of(lifespan, &biological_clock) {
+            Some(new_deadline) => {
+                deadlines.anchor(biological_clock.bypass_change_detection());
+                // the deadline only moves when the clock or the lifespan really changed
+                if deadline != Some(&new_deadline) {
+                    deadlines.push(entity, new_deadline);
+                    commands.entity(entity).insert(new_deadline);
+                }
+            }
+            None => {
+                if matches!(lifespan, Lifespan::Ticks(_) | Lifespan::UntilCondition) {
+                    warn!("Lifespan {:?} is not supported by the deadline backend.", lifespan);
+                }
+                commands.entity(entity).remove::<LifetimeDeadline>();
+            }
+        }
+    }
+}
+
+/// Acts like a garbage collector to remove entities whose deadline has passed, advanced with the clock `C`
+pub fn expire_by_deadline<T, C>(mut commands: Commands, clock: Res<C>, mut deadlines: ResMut<LifetimeDeadlines<T>>, queries: Query<&LifetimeDeadline, With<T>>)
+where
+    T: Component,
+    C: LifetimeClock,
+{
+    for (entity, deadline) in deadlines.advance(clock.delta()) {
+        // stale entries belong to entities that were renewed, paused or despawned
+        if queries.get(entity) == Ok(&deadline) {
+            expire_entity(&mut commands, entity);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LifetimeBackend, LifetimePlugin};
+    use bevy_app::prelude::*;
+
+    /// Clock that moves by the same delta on every update.
+    #[derive(Resource)]
+    struct FixedStepClock(Duration);
+
+    impl LifetimeClock for FixedStepClock {
+        fn delta(&self) -> Duration {
+            self.0
+        }
+    }
+
+    #[derive(Component)]
+    struct Mayfly(Lifespan);
+
+    impl BiologicalTrait for Mayfly {
+        fn get_lifespan(&self) -> Lifespan {
+            self.0
+        }
+    }
+
+    fn app() -> App {
+        let mut app = App::new();
+        app.insert_resource(FixedStepClock(Duration::from_secs(1)));
+        app.add_plugins(LifetimePlugin::<Mayfly, FixedStepClock>::default().with_backend(LifetimeBackend::Deadline));
+        app
+    }
+
+    fn update(app: &mut App, times: usize) {
+        for _ in 0..times {
+            app.update();
+        }
+    }
+
+    #[test]
+    fn clock_ages_without_being_ticked() {
+        let mut app = app();
+        let entity = app.world_mut().spawn(Mayfly(Lifespan::Duration(Duration::from_secs(10)))).id();
+        update(&mut app, 6);
+
+        let biological_clock = app.world().get::<BiologicalClock>(entity).unwrap();
+        assert_eq!(biological_clock.age(), Duration::from_secs(6));
+        assert_eq!(biological_clock.lived_frames(), 6);
+        assert_eq!(biological_clock.lifetime.elapsed(), Duration::ZERO);
+    }
+
+    #[test]
+    fn expires_once_the_deadline_has_passed() {
+        let mut app = app();
+        let duration = app.world_mut().spawn(Mayfly(Lifespan::Duration(Duration::from_secs(3)))).id();
+        let frames = app.world_mut().spawn(Mayfly(Lifespan::Frames(5))).id();
+        update(&mut app, 4);
+        assert!(app.world().get_entity(duration).is_ok());
+        update(&mut app, 1);
+        assert!(app.world().get_entity(duration).is_err());
+        assert!(app.world().get_entity(frames).is_ok());
+        update(&mut app, 1);
+        assert!(app.world().get_entity(frames).is_err());
+    }
+
+    #[test]
+    fn renewed_entities_skip_their_stale_deadline() {
+        let mut app = app();
+        let entity = app.world_mut().spawn(Mayfly(Lifespan::Duration(Duration::from_secs(3)))).id();
+        update(&mut app, 3);
+        app.world_mut().get_mut::<BiologicalClock>(entity).unwrap().reset();
+        update(&mut app, 3);
+        assert!(app.world().get_entity(entity).is_ok());
+        assert_eq!(app.world().resource::<LifetimeDeadlines<Mayfly>>().len(), 1);
+        update(&mut app, 2);
+        assert!(app.world().get_entity(entity).is_err());
+    }
+
+    #[test]
+    fn paused_clocks_keep_their_age() {
+        let mut app = app();
+        let entity = app.world_mut().spawn(Mayfly(Lifespan::Duration(Duration::from_secs(3)))).id();
+        update(&mut app, 3);
+        app.world_mut().get_mut::<BiologicalClock>(entity).unwrap().lifetime.pause();
+        update(&mut app, 10);
+        assert_eq!(app.world().get::<BiologicalClock>(entity).unwrap().age(), Duration::from_secs(3));
+        assert!(app.world().get::<LifetimeDeadline>(entity).is_none());
+
+        app.world_mut().get_mut::<BiologicalClock>(entity).unwrap().lifetime.unpause();
+        update(&mut app, 1);
+        assert!(app.world().get_entity(entity).is_ok());
+        update(&mut app, 1);
+        assert!(app.world().get_entity(entity).is_err());
+    }
+
+    #[test]
+    fn queues_stay_bounded_when_clocks_change_every_frame() {
+        let mut app = app();
+        app.add_systems(PreUpdate, |mut queries: Query<&mut BiologicalClock>| {
+            for mut biological_clock in queries.iter_mut() {
+                biological_clock.tick(Duration::ZERO);
+            }
+        });
+        for _ in 0..10 {
+            app.world_mut().spawn(Mayfly(Lifespan::Frames(u64::MAX / 2)));
+        }
+        update(&mut app, 200);
+        assert!(app.world().resource::<LifetimeDeadlines<Mayfly>>().len() <= 2 * 10 + 64 + 10);
+    }
+}
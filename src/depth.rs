@@ -0,0 +1,101 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+use bevy_ecs::entity::EntityHashSet;
+use bevy_ecs::prelude::*;
+use bevy_hierarchy::prelude::*;
+
+use crate::Identifier;
+
+/// Number of ancestors of the entity, 0 for roots, 1 for their children and so on.
+///
+/// Maintained by the `FamilyPlugin` for every entity with an identifier, when it is created, moved or orphaned.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FamilyDepth(pub usize);
+
+/// Filter for the entities with an identifier that are new or have moved to another parent.
+type NewOrMoved<T> = (With<Identifier<T>>, Or<(Added<Identifier<T>>, Changed<Parent>)>);
+
+/// Updates the depth of the entities that were created, moved or orphaned, along with their descendants with an identifier
+pub fn update_family_depths<T>(
+    mut commands: Commands,
+    changed_queries: Query<Entity, NewOrMoved<T>>,
+    mut orphaned: RemovedComponents<Parent>,
+    member_queries: Query<(), With<Identifier<T>>>,
+    parent_queries: Query<&Parent>,
+    children_queries: Query<&Children>,
+    depth_queries: Query<&FamilyDepth>,
+) where
+    T: Send + Sync + 'static,
+{
+    let mut moved: Vec<Entity> = changed_queries.iter().collect();
+    moved.extend(orphaned.read().filter(|entity| member_queries.contains(*entity)));
+
+    let mut updated = EntityHashSet::default();
+    for entity in moved {
+        if updated.contains(&entity) {
+            continue;
+        }
+        let mut stack = vec![(entity, parent_queries.iter_ancestors(entity).count())];
+        while let Some((entity, depth)) = stack.pop() {
+            updated.insert(entity);
+            if depth_queries.get(entity).ok() != Some(&FamilyDepth(depth)) {
+                commands.entity(entity).insert(FamilyDepth(depth));
+            }
+            if let Ok(children) = children_queries.get(entity) {
+                stack.extend(children.iter().filter(|child| member_queries.contains(**child)).map(|child| (*child, depth + 1)));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{app, entity, send, Node};
+    use crate::CudEvent;
+    use bevy_app::prelude::*;
+
+    fn depth(app: &mut App, identifier: &str) -> Option<usize> {
+        let entity = entity(app, identifier);
+        app.world().get::<FamilyDepth>(entity).map(|depth| depth.0)
+    }
+
+    #[test]
+    fn follows_the_members_as_they_move() {
+        let mut app = app();
+        send(&mut app, CudEvent::create_parent("P".to_string(), Node::default()));
+        send(&mut app, CudEvent::create_child("P".to_string(), "A".to_string(), Node::default()));
+        send(&mut app, CudEvent::create_child("A".to_string(), "B".to_string(), Node::default()));
+        send(&mut app, CudEvent::create_parent("Q".to_string(), Node::default()));
+        let a = entity(&mut app, "A");
+        let other = app.world_mut().spawn_empty().set_parent(a).id();
+        app.update();
+        assert_eq!([depth(&mut app, "P"), depth(&mut app, "A"), depth(&mut app, "B")], [Some(0), Some(1), Some(2)]);
+
+        let (b, q) = (entity(&mut app, "B"), entity(&mut app, "Q"));
+        app.world_mut().entity_mut(b).set_parent(q);
+        app.update();
+        assert_eq!(depth(&mut app, "B"), Some(1));
+
+        app.world_mut().entity_mut(a).remove_parent();
+        app.update();
+        assert_eq!(depth(&mut app, "A"), Some(0));
+        assert_eq!(app.world().get::<FamilyDepth>(other), None);
+    }
+}
@@ -0,0 +1,221 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+use bevy_ecs::prelude::*;
+use bevy_hierarchy::prelude::*;
+use disqualified::ShortName;
+
+use std::any::TypeId;
+use std::fmt::{Debug, Write};
+use std::time::Duration;
+
+use crate::{BiologicalClock, Identifier};
+
+/// An entity with an identifier, as it is exported with its descendants.
+///
+/// With the `serde` feature, the entity is serialized as its id and the age in seconds.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ExportedNode {
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_entity"))]
+    pub entity: Entity,
+    /// Identifier formatted with `Debug`.
+    pub identifier: String,
+    /// Short type names of the components, without the identifier and the hierarchy.
+    pub components: Vec<String>,
+    /// Age from the `BiologicalClock`, if the entity ages.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_age"))]
+    pub age: Option<Duration>,
+    pub children: Vec<ExportedNode>,
+}
+
+/// Serialize the entity as its id, like `1v1`
+#[cfg(feature = "serde")]
+fn serialize_entity<S>(entity: &Entity, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.collect_str(entity)
+}
+
+/// Serialize the age in seconds
+#[cfg(feature = "serde")]
+fn serialize_age<S>(age: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serde::Serialize::serialize(&age.map(|age| age.as_secs_f64()), serializer)
+}
+
+/// Collect the family trees of the entities with the identifier `T`, from their roots.
+///
+/// Roots are the entities without a parent with an identifier, ordered by entity.
+pub fn collect_family_trees<T>(world: &World) -> Vec<ExportedNode>
+where
+    T: Debug + Send + Sync + 'static,
+{
+    let mut roots: Vec<Entity> = world
+        .iter_entities()
+        .filter(|entity_ref| entity_ref.contains::<Identifier<T>>())
+        .filter(|entity_ref| entity_ref.get::<Parent>().is_none_or(|parent| !world.entity(parent.get()).contains::<Identifier<T>>()))
+        .map(|entity_ref| entity_ref.id())
+        .collect();
+    roots.sort();
+    roots.into_iter().map(|root| collect_node::<T>(world, root)).collect()
+}
+
+/// Collect the entity with its descendants that have an identifier
+fn collect_node<T>(world: &World, entity: Entity) -> ExportedNode
+where
+    T: Debug + Send + Sync + 'static,
+{
+    let entity_ref = world.entity(entity);
+    let hidden = [TypeId::of::<Identifier<T>>(), TypeId::of::<Parent>(), TypeId::of::<Children>()];
+    let components = entity_ref
+        .archetype()
+        .components()
+        .filter_map(|component_id| world.components().get_info(component_id))
+        .filter(|info| info.type_id().is_none_or(|type_id| !hidden.contains(&type_id)))
+        .map(|info| ShortName(info.name()).to_string())
+        .collect();
+    let children = entity_ref
+        .get::<Children>()
+        .into_iter()
+        .flatten()
+        .filter(|child| world.entity(**child).contains::<Identifier<T>>())
+        .map(|child| collect_node::<T>(world, *child))
+        .collect();
+    ExportedNode {
+        entity,
+        identifier: entity_ref.get::<Identifier<T>>().map_or_else(String::new, |identifier| format!("{:?}", identifier.0)),
+        components,
+        age: entity_ref.get::<BiologicalClock>().map(|biological_clock| biological_clock.age()),
+        children,
+    }
+}
+
+/// Export the family trees of the identifier `T` as a Graphviz DOT graph.
+pub fn export_dot<T>(world: &World) -> String
+where
+    T: Debug + Send + Sync + 'static,
+{
+    let mut dot = String::from("digraph family {\n");
+    let mut stack: Vec<&ExportedNode> = Vec::new();
+    let roots = collect_family_trees::<T>(world);
+    stack.extend(roots.iter().rev());
+    while let Some(node) = stack.pop() {
+        let mut label = format!("{}\n{}", node.identifier, node.entity);
+        if !node.components.is_empty() {
+            write!(label, "\n{}", node.components.join(", ")).unwrap();
+        }
+        if let Some(age) = node.age {
+            write!(label, "\nage {:.2?}", age).unwrap();
+        }
+        writeln!(dot, "    \"{}\" [label=\"{}\"];", node.entity, escape_dot(&label)).unwrap();
+        for child in &node.children {
+            writeln!(dot, "    \"{}\" -> \"{}\";", node.entity, child.entity).unwrap();
+        }
+        stack.extend(node.children.iter().rev());
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// Export the family trees of the identifier `T` as a JSON array of nested nodes.
+#[cfg(feature = "export_json")]
+pub fn export_json<T>(world: &World) -> String
+where
+    T: Debug + Send + Sync + 'static,
+{
+    serde_json::to_string(&collect_family_trees::<T>(world)).expect("exported nodes are always serializable")
+}
+
+/// Export the family trees of the identifier `T` as indented text, like the `tree` command.
+pub fn export_text<T>(world: &World) -> String
+where
+    T: Debug + Send + Sync + 'static,
+{
+    let mut text = String::new();
+    for root in collect_family_trees::<T>(world) {
+        write_text_node(&mut text, &root, "", "");
+    }
+    text
+}
+
+/// Write the node on its own line, and its children below it
+fn write_text_node(text: &mut String, node: &ExportedNode, prefix: &str, children_prefix: &str) {
+    write!(text, "{}{} [{}]", prefix, node.identifier, node.entity).unwrap();
+    if !node.components.is_empty() {
+        write!(text, " ({})", node.components.join(", ")).unwrap();
+    }
+    if let Some(age) = node.age {
+        write!(text, " age {:.2?}", age).unwrap();
+    }
+    text.push('\n');
+    for (index, child) in node.children.iter().enumerate() {
+        let last = index + 1 == node.children.len();
+        let (branch, indent) = if last { ("└── ", "    ") } else { ("├── ", "│   ") };
+        write_text_node(text, child, &format!("{}{}", children_prefix, branch), &format!("{}{}", children_prefix, indent));
+    }
+}
+
+/// Escape the text to be quoted in a DOT label, leaving out the control characters that DOT cannot escape
+fn escape_dot(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for character in text.chars() {
+        match character {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            character if character.is_control() => {}
+            character => escaped.push(character),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn world() -> World {
+        let mut world = World::new();
+        let root = world.spawn(Identifier("Tab\tand \"quote\"".to_string())).id();
+        let child = world.spawn(Identifier("Child".to_string())).id();
+        world.entity_mut(root).add_child(child);
+        world
+    }
+
+    #[test]
+    fn dot_labels_are_escaped_for_dot() {
+        let dot = export_dot::<String>(&world());
+        assert!(dot.contains(r#"[label="\"Tab\\tand \\\"quote\\\"\"\n"#));
+        assert_eq!(escape_dot("a\u{1b}b\n\"c\"\\"), r#"ab\n\"c\"\\"#);
+    }
+
+    #[cfg(feature = "export_json")]
+    #[test]
+    fn json_nodes_are_nested() {
+        let world = world();
+        let json: serde_json::Value = serde_json::from_str(&export_json::<String>(&world)).unwrap();
+        assert_eq!(json[0]["identifier"], "\"Tab\\tand \\\"quote\\\"\"");
+        assert_eq!(json[0]["age"], serde_json::Value::Null);
+        assert_eq!(json[0]["children"][0]["identifier"], "\"Child\"");
+        assert_eq!(json[0]["children"][0]["entity"], collect_family_trees::<String>(&world)[0].children[0].entity.to_string());
+    }
+}
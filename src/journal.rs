@@ -0,0 +1,346 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
+use bevy_log::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use crate::{History, Lineage};
+
+/// Resource that appends the histories of the `Lineage` to a journal file, one JSON history per line.
+///
+/// Once the file grows over the maximum size, it is rotated to `<path>.1`, `<path>.2` and so on, keeping up to the maximum number of rotated files.
+#[derive(Resource)]
+pub struct LineageJournal<T> {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    size: u64,
+    /// Sequence of the first history that has not been written yet.
+    next_sequence: u64,
+    file: Option<File>,
+    _marker: PhantomData<T>,
+}
+
+impl<T> LineageJournal<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Create the journal at the path, without opening the file yet.
+    pub fn new(path: impl Into<PathBuf>, max_size: u64, max_files: usize) -> Self {
+        Self {
+            path: path.into(),
+            max_size,
+            max_files,
+            size: 0,
+            next_sequence: 0,
+            file: None,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Get the path of the current journal file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Get the path of the rotated journal file, 1 being the most recent.
+    pub fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        path.into()
+    }
+
+    /// Load the histories from the rotated journal files and the current journal file, oldest first.
+    ///
+    /// Lines that cannot be parsed, like a line cut short by a crash, are skipped.
+    pub fn load(&self) -> io::Result<Vec<History<T>>> {
+        let mut histories = Vec::new();
+        let paths = (1..=self.max_files).rev().map(|index| self.rotated_path(index)).chain(std::iter::once(self.path.clone()));
+        for path in paths {
+            let file = match File::open(&path) {
+                Ok(file) => file,
+                Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+                Err(error) => return Err(error),
+            };
+            for line in BufReader::new(file).lines() {
+                let line = line?;
+                match serde_json::from_str(&line) {
+                    Ok(history) => histories.push(history),
+                    Err(error) => warn!("Skipped a history in the journal {:?}: {}.", path, error),
+                }
+            }
+        }
+        Ok(histories)
+    }
+
+    /// Append the histories that have not been written yet to the journal file.
+    ///
+    /// The histories are tracked by their sequence, so the histories that are removed from the lineage stay in the journal and do not hold back the new ones.
+    pub fn write(&mut self, lineage: &Lineage<T>) -> io::Result<()> {
+        let mut lines = Vec::new();
+        let mut next_sequence = self.next_sequence;
+        for history in lineage.histories.iter().filter(|history| history.sequence >= self.next_sequence) {
+            serde_json::to_writer(&mut lines, history)?;
+            lines.push(b'\n');
+            next_sequence = next_sequence.max(history.sequence + 1);
+        }
+        if lines.is_empty() {
+            return Ok(());
+        }
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => {
+                let mut file = OpenOptions::new().create(true).read(true).append(true).open(&self.path)?;
+                self.size = file.metadata()?.len();
+                // a line cut short by a crash is ended, so that the next history does not join it
+                if self.size > 0 {
+                    let mut last = [0];
+                    file.seek(SeekFrom::End(-1))?;
+                    file.read_exact(&mut last)?;
+                    if last[0] != b'\n' {
+                        file.write_all(b"\n")?;
+                        self.size += 1;
+                    }
+                }
+                self.file.insert(file)
+            }
+        };
+        file.write_all(&lines)?;
+        file.flush()?;
+        self.size += lines.len() as u64;
+        self.next_sequence = next_sequence;
+
+        if self.size >= self.max_size {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+
+    /// Put the histories of the journal before the histories of the lineage, which follow them in sequence and are written next.
+    fn reload(&mut self, lineage: &mut Lineage<T>, histories: Vec<History<T>>) {
+        self.next_sequence = histories.iter().map(|history| history.sequence + 1).max().unwrap_or(0);
+        lineage.next_sequence = self.next_sequence;
+        for mut history in std::mem::replace(&mut lineage.histories, histories) {
+            history.sequence = lineage.next_sequence;
+            lineage.next_sequence += 1;
+            lineage.histories.push(history);
+        }
+    }
+
+    /// Move the current journal file to the first rotated file, shifting the older ones and removing the oldest.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file = None;
+        self.size = 0;
+        if self.max_files == 0 {
+            return fs::remove_file(&self.path);
+        }
+        for index in (1..self.max_files).rev() {
+            let from = self.rotated_path(index);
+            if from.exists() {
+                fs::rename(from, self.rotated_path(index + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated_path(1))
+    }
+}
+
+/// Plugin that keeps an append-only journal of the `Lineage` of the identifier `T` on disk
+///
+/// The histories of every frame are appended in `Last`. When the journal is reloaded, the `Lineage` starts with the histories of the journal.
+pub struct JournalPlugin<T> {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    reload: bool,
+    _marker: PhantomData<T>,
+}
+
+impl<T> JournalPlugin<T> {
+    /// Create the plugin that writes the journal to the path, rotated at 10 MiB with 3 rotated files, and reloaded on startup.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            max_size: 10 * 1024 * 1024,
+            max_files: 3,
+            reload: true,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Rotate the journal once it is larger than the size in bytes.
+    pub fn with_max_size(mut self, max_size: u64) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Keep up to the number of rotated journal files.
+    pub fn with_max_files(mut self, max_files: usize) -> Self {
+        self.max_files = max_files;
+        self
+    }
+
+    /// Select whether the `Lineage` starts with the histories of the journal.
+    pub fn with_reload(mut self, reload: bool) -> Self {
+        self.reload = reload;
+        self
+    }
+}
+
+impl<T> Plugin for JournalPlugin<T>
+where
+    T: Clone + PartialEq + Default + Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    /// Build the plugin
+    fn build(&self, app: &mut App) {
+        let mut journal = LineageJournal::<T>::new(self.path.clone(), self.max_size, self.max_files);
+        if self.reload {
+            match journal.load() {
+                Ok(histories) => {
+                    debug!("Reloaded {} histories from the journal {:?}.", histories.len(), journal.path());
+                    let mut lineage = app.world_mut().get_resource_or_insert_with(Lineage::<T>::default);
+                    journal.reload(&mut lineage, histories);
+                }
+                Err(error) => warn!("Journal {:?} cannot be reloaded: {}.", journal.path(), error),
+            }
+        }
+        app.insert_resource(journal);
+        app.add_systems(Last, write_lineage_journal::<T>);
+    }
+}
+
+/// Appends the new histories of the lineage to the journal
+pub fn write_lineage_journal<T>(lineage: Res<Lineage<T>>, mut journal: ResMut<LineageJournal<T>>)
+where
+    T: Clone + PartialEq + Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    if let Err(error) = journal.write(&lineage) {
+        warn!("Journal {:?} cannot be written: {}.", journal.path(), error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Action, Identifier};
+
+    fn journal_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("bevy_family_journal_{}_{}", name, std::process::id()));
+        for index in 0..=3 {
+            let mut rotated = path.clone().into_os_string();
+            if index > 0 {
+                rotated.push(format!(".{}", index));
+            }
+            let _ = fs::remove_file(rotated);
+        }
+        path
+    }
+
+    fn create(lineage: &mut Lineage<String>, identifier: &str) {
+        lineage.add_history(History::new(Action::Create, None, Identifier(identifier.to_string()), Ok(())));
+    }
+
+    fn identifiers(histories: &[History<String>]) -> Vec<String> {
+        histories.iter().map(|history| history.parent_identifier.0.clone()).collect()
+    }
+
+    #[test]
+    fn writes_new_histories_after_removed_ones() {
+        let mut journal = LineageJournal::<String>::new(journal_path("removed"), u64::MAX, 3);
+        let mut lineage = Lineage::<String>::default();
+        create(&mut lineage, "A");
+        create(&mut lineage, "B");
+        journal.write(&lineage).unwrap();
+        lineage.pop();
+        create(&mut lineage, "C");
+        journal.write(&lineage).unwrap();
+        lineage.clear_history();
+        create(&mut lineage, "D");
+        journal.write(&lineage).unwrap();
+        journal.write(&lineage).unwrap();
+
+        assert_eq!(identifiers(&journal.load().unwrap()), ["A", "B", "C", "D"]);
+    }
+
+    #[test]
+    fn rotates_and_loads_oldest_first() {
+        let path = journal_path("rotated");
+        let mut journal = LineageJournal::<String>::new(path.clone(), 1, 2);
+        let mut lineage = Lineage::<String>::default();
+        for identifier in ["A", "B", "C", "D"] {
+            create(&mut lineage, identifier);
+            journal.write(&lineage).unwrap();
+        }
+
+        assert!(!path.exists());
+        assert!(journal.rotated_path(1).exists());
+        assert!(journal.rotated_path(2).exists());
+        assert!(!journal.rotated_path(3).exists());
+        assert_eq!(identifiers(&journal.load().unwrap()), ["C", "D"]);
+    }
+
+    #[test]
+    fn skips_lines_cut_short() {
+        let path = journal_path("cut");
+        let mut journal = LineageJournal::<String>::new(path.clone(), u64::MAX, 3);
+        let mut lineage = Lineage::<String>::default();
+        create(&mut lineage, "A");
+        journal.write(&lineage).unwrap();
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(b"{\"action\":").unwrap();
+        assert_eq!(identifiers(&journal.load().unwrap()), ["A"]);
+
+        // the journal is opened again after the crash
+        let mut journal = LineageJournal::<String>::new(path, u64::MAX, 3);
+        let mut lineage = Lineage::<String>::default();
+        let histories = journal.load().unwrap();
+        journal.reload(&mut lineage, histories);
+        create(&mut lineage, "B");
+        create(&mut lineage, "C");
+        journal.write(&lineage).unwrap();
+        assert_eq!(identifiers(&journal.load().unwrap()), ["A", "B", "C"]);
+    }
+
+    #[test]
+    fn reload_continues_the_sequence() {
+        let path = journal_path("reload");
+        let mut journal = LineageJournal::<String>::new(path.clone(), u64::MAX, 3);
+        let mut lineage = Lineage::<String>::default();
+        create(&mut lineage, "A");
+        create(&mut lineage, "B");
+        journal.write(&lineage).unwrap();
+
+        let mut journal = LineageJournal::<String>::new(path, u64::MAX, 3);
+        let mut lineage = Lineage::<String>::default();
+        create(&mut lineage, "C");
+        let histories = journal.load().unwrap();
+        journal.reload(&mut lineage, histories);
+        assert_eq!(identifiers(&lineage.histories), ["A", "B", "C"]);
+        assert_eq!(lineage.histories.iter().map(|history| history.sequence).collect::<Vec<_>>(), [0, 1, 2]);
+
+        create(&mut lineage, "D");
+        journal.write(&lineage).unwrap();
+        assert_eq!(identifiers(&journal.load().unwrap()), ["A", "B", "C", "D"]);
+    }
+}
@@ -23,6 +23,56 @@ use bevy_log::prelude::*;
 use bevy_time::prelude::*;
 
 use std::marker::PhantomData;
+use std::time::Duration;
+
+mod age;
+mod ancestors;
+#[cfg(feature = "asset")]
+mod asset;
+mod auto_delete;
+mod constraint;
+mod deadline;
+mod depth;
+mod export;
+#[cfg(feature = "journal")]
+mod journal;
+mod lifetime;
+mod path;
+mod population;
+mod query;
+mod recording;
+mod reincarnation;
+#[cfg(feature = "event_sourcing")]
+mod replay;
+mod reproduction;
+mod snapshot;
+mod stage;
+#[cfg(test)]
+mod testing;
+mod undo;
+pub use age::*;
+pub use ancestors::*;
+#[cfg(feature = "asset")]
+pub use asset::*;
+pub use auto_delete::*;
+pub use constraint::*;
+pub use deadline::*;
+pub use depth::*;
+pub use export::*;
+#[cfg(feature = "journal")]
+pub use journal::*;
+pub use lifetime::*;
+pub use path::*;
+pub use population::*;
+pub use query::*;
+pub use recording::*;
+pub use reincarnation::*;
+#[cfg(feature = "event_sourcing")]
+pub use replay::*;
+pub use reproduction::*;
+pub use snapshot::*;
+pub use stage::*;
+pub use undo::*;
 
 /// Main Plugin for the Family Plugin
 ///
@@ -34,36 +84,171 @@ pub struct FamilyPlugin<T> {
 
 impl<T> Plugin for FamilyPlugin<T>
 where
-    T: Default + Send + Sync + 'static,
+    T: Clone + std::fmt::Debug + PartialEq + Default + Send + Sync + 'static,
 {
     /// Build the plugin
     fn build(&self, app: &mut App) {
         app.init_resource::<Lineage<T>>();
+        app.init_resource::<Generations<T>>();
+        app.add_event::<Evicted<T>>();
+        app.add_event::<AgingEvent<T>>();
+        app.add_systems(PreUpdate, attach_or_detach_aging::<T>);
+        app.add_observer(record_expiry::<T>);
+        app.add_systems(PostUpdate, update_family_depths::<T>);
+        app.add_systems(First, advance_lineage_time::<T>);
     }
 }
 
-pub trait BiologicalTrait {
-    fn get_lifetime(&self) -> std::time::Duration;
-}
-
 /// Action to interact with the entities.
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Action {
     Create,
     CreateOrModify,
     Update,
     Delete,
     Clear,
+    /// Renew the entity's `BiologicalClock`, keeping its lease alive.
+    Touch,
+    /// The entity exceeded its lifetime.
+    Expire,
+    /// The entity is reborn after expiring, with the generation that it is reborn as.
+    Reincarnate(u32),
+    /// The entity is removed to make room for a new entity under a population cap.
+    Evict,
+    /// Create the entity along with its missing ancestors.
+    CreateWithAncestors,
+    /// The entity is brought back to its state before an operation that is undone.
+    Undo,
+    /// The entity is brought back to its state after an operation that is redone.
+    Redo,
+}
+
+/// Reason that an action on the entities has failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FamilyError {
+    /// The parent entity does not exist.
+    ParentNotFound,
+    /// The entity does not exist.
+    NotFound,
+    /// The entity already exists.
+    AlreadyExists,
+    /// The population cap of the bundle has been reached.
+    PopulationCapReached,
+    /// There is no history of the entity in the lineage.
+    NoHistory,
+    /// The entity would be deeper than the maximum depth.
+    MaxDepthExceeded,
+    /// The parent already has the maximum number of children.
+    MaxChildrenExceeded,
+    /// The tree of the parent already has the maximum number of members.
+    MaxMembersExceeded,
+}
+
+impl std::fmt::Display for FamilyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FamilyError::ParentNotFound => write!(f, "parent entity does not exist"),
+            FamilyError::NotFound => write!(f, "entity does not exist"),
+            FamilyError::AlreadyExists => write!(f, "entity already exists"),
+            FamilyError::PopulationCapReached => write!(f, "population cap has been reached"),
+            FamilyError::NoHistory => write!(f, "entity has no history in the lineage"),
+            FamilyError::MaxDepthExceeded => write!(f, "maximum depth has been exceeded"),
+            FamilyError::MaxChildrenExceeded => write!(f, "maximum number of children has been reached"),
+            FamilyError::MaxMembersExceeded => write!(f, "maximum number of members in the tree has been reached"),
+        }
+    }
 }
 
+impl std::error::Error for FamilyError {}
+
 /// Unique identifier for the entities.
 #[derive(Component, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Identifier<T>(pub T);
 
-/// A component that represents the entity's lifetime.
-#[derive(Component, Default)]
-pub struct BiologicalClock {
-    pub lifetime: bevy_time::Stopwatch,
+/// Number of times that an identifier has been spawned in its scope, starting from 1.
+///
+/// Compare it with a generation that was stored earlier to detect that the entity has been recreated since.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Generation(pub u32);
+
+/// Resource that keeps the latest generation of every identifier, scoped by its parent identifier
+///
+/// The generations are kept in a list that is searched linearly and grows with every identifier that has ever been spawned,
+/// as identifiers are only required to be `PartialEq`. Apps that spawn many short-lived identifiers should `retain` the ones that still matter.
+#[derive(Resource)]
+pub struct Generations<T> {
+    entries: Vec<(Option<Identifier<T>>, Identifier<T>, u32)>,
+}
+
+impl<T> Default for Generations<T> {
+    fn default() -> Self {
+        Self { entries: Vec::new() }
+    }
+}
+
+impl<T> Generations<T>
+where
+    T: Clone + PartialEq,
+{
+    /// Get the latest generation of the identifier, 0 if it has never been spawned.
+    pub fn get(&self, parent_identifier: &Option<Identifier<T>>, self_identifier: &Identifier<T>) -> u32 {
+        for (parent, identifier, generation) in &self.entries {
+            if parent == parent_identifier && identifier == self_identifier {
+                return *generation;
+            }
+        }
+        0
+    }
+
+    /// Check if a generation of the identifier has been replaced by a newer one.
+    pub fn is_stale(&self, parent_identifier: &Option<Identifier<T>>, self_identifier: &Identifier<T>, generation: Generation) -> bool {
+        generation.0 < self.get(parent_identifier, self_identifier)
+    }
+
+    /// Increase the generation of the identifier, returning the new generation.
+    pub fn next(&mut self, parent_identifier: &Option<Identifier<T>>, self_identifier: &Identifier<T>) -> u32 {
+        for (parent, identifier, generation) in self.entries.iter_mut() {
+            if parent == parent_identifier && identifier == self_identifier {
+                *generation += 1;
+                return *generation;
+            }
+        }
+        self.entries.push((parent_identifier.clone(), self_identifier.clone(), 1));
+        1
+    }
+
+    /// Number of identifiers with a generation.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if no identifier has been spawned.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Forget the generations of the identifiers that do not match the predicate, so they start again from 1 the next time they are spawned.
+    ///
+    /// A generation that was stored before its identifier is forgotten is no longer seen as stale, so only forget identifiers that nothing holds on to.
+    pub fn retain(&mut self, mut keep: impl FnMut(&Option<Identifier<T>>, &Identifier<T>, u32) -> bool) {
+        self.entries.retain(|(parent, identifier, generation)| keep(parent, identifier, *generation));
+    }
+}
+
+/// Get the entity by the identifier, only if it is still of the given generation.
+pub fn get_entity_by_identifier_and_generation<U>(queries: &Query<(Entity, &Identifier<U>, &Generation)>, identifier: &Identifier<U>, generation: Generation) -> Option<Entity>
+where
+    U: PartialEq + Send + Sync + 'static,
+{
+    for (entity, id, entity_generation) in queries.iter() {
+        if id == identifier && *entity_generation == generation {
+            return Some(entity);
+        }
+    }
+    None
 }
 
 /// Event that is used to create, update and delete parent entities
@@ -71,6 +256,7 @@ pub struct BiologicalClock {
 pub struct CudEvent<U: Bundle, T> {
     action: Action,
     parent_identifier: Option<Identifier<T>>,
+    path: Option<FamilyPath<T>>,
     self_identifier: Identifier<T>,
     bundle: U,
 }
@@ -84,6 +270,11 @@ where
         &self.parent_identifier
     }
 
+    /// Get the path of the entity, if the event was created from a path.
+    pub fn get_path(&self) -> &Option<FamilyPath<T>> {
+        &self.path
+    }
+
     pub fn get_self_identifier(&self) -> &Identifier<T> {
         &self.self_identifier
     }
@@ -96,17 +287,19 @@ where
         self.bundle.clone()
     }
 
-    pub fn to_history(&self, result: Result<(), ()>) -> History<T> {
-        match &self.parent_identifier {
-            Some(parent_identifier) => History::new_child_history(self.action.clone(), parent_identifier.clone(), self.self_identifier.clone(), result),
-            None => History::new_parent_history(self.action.clone(), self.self_identifier.clone(), result),
-        }
+    pub fn get_bundle_ref(&self) -> &U {
+        &self.bundle
+    }
+
+    pub fn to_history(&self, result: Result<(), FamilyError>) -> History<T> {
+        History::new(self.action.clone(), self.parent_identifier.clone(), self.self_identifier.clone(), result)
     }
 
     pub fn create_parent(self_identifier: T, bundle: U) -> Self {
         Self {
             action: Action::Create,
             parent_identifier: None,
+            path: None,
             self_identifier: Identifier(self_identifier),
             bundle,
         }
@@ -115,6 +308,7 @@ where
         Self {
             action: Action::CreateOrModify,
             parent_identifier: None,
+            path: None,
             self_identifier: Identifier(self_identifier),
             bundle,
         }
@@ -123,6 +317,7 @@ where
         Self {
             action: Action::Update,
             parent_identifier: None,
+            path: None,
             self_identifier: Identifier(self_identifier),
             bundle,
         }
@@ -131,6 +326,7 @@ where
         Self {
             action: Action::Delete,
             parent_identifier: None,
+            path: None,
             self_identifier: Identifier(self_identifier),
             bundle,
         }
@@ -139,6 +335,16 @@ where
         Self {
             action: Action::Clear,
             parent_identifier: None,
+            path: None,
+            self_identifier: Identifier(self_identifier),
+            bundle,
+        }
+    }
+    pub fn touch_parent(self_identifier: T, bundle: U) -> Self {
+        Self {
+            action: Action::Touch,
+            parent_identifier: None,
+            path: None,
             self_identifier: Identifier(self_identifier),
             bundle,
         }
@@ -149,6 +355,7 @@ where
             action: Action::Create,
             self_identifier: Identifier(self_identifier),
             parent_identifier: Some(Identifier(parent_identifier)),
+            path: None,
             bundle,
         }
     }
@@ -157,6 +364,7 @@ where
             action: Action::CreateOrModify,
             self_identifier: Identifier(self_identifier),
             parent_identifier: Some(Identifier(parent_identifier)),
+            path: None,
             bundle,
         }
     }
@@ -165,6 +373,7 @@ where
             action: Action::Update,
             self_identifier: Identifier(self_identifier),
             parent_identifier: Some(Identifier(parent_identifier)),
+            path: None,
             bundle,
         }
     }
@@ -173,6 +382,7 @@ where
             action: Action::Delete,
             self_identifier: Identifier(self_identifier),
             parent_identifier: Some(Identifier(parent_identifier)),
+            path: None,
             bundle,
         }
     }
@@ -181,38 +391,123 @@ where
             action: Action::Clear,
             self_identifier: Identifier(self_identifier),
             parent_identifier: Some(Identifier(parent_identifier)),
+            path: None,
+            bundle,
+        }
+    }
+    pub fn touch_child(parent_identifier: T, self_identifier: T, bundle: U) -> Self {
+        Self {
+            action: Action::Touch,
+            self_identifier: Identifier(self_identifier),
+            parent_identifier: Some(Identifier(parent_identifier)),
+            path: None,
             bundle,
         }
     }
+
+    /// Create the event for the entity that the path leads to, as a parent if the path leads to a root.
+    ///
+    /// The entity is looked up by its whole path instead of its identifier, so a root path only matches an entity without a parent.
+    fn at_path(action: Action, path: FamilyPath<T>, bundle: U) -> Self {
+        Self {
+            action,
+            parent_identifier: path.parent_identifier().cloned(),
+            self_identifier: path.leaf().clone(),
+            path: Some(path),
+            bundle,
+        }
+    }
+    /// Create the event that brings an expired entity back to life, creating or replacing it like `create_or_modify_*`.
+    pub(crate) fn reincarnate(parent_identifier: Option<Identifier<T>>, self_identifier: Identifier<T>, generation: u32, bundle: U) -> Self {
+        Self {
+            action: Action::Reincarnate(generation),
+            parent_identifier,
+            path: None,
+            self_identifier,
+            bundle,
+        }
+    }
+    pub fn create_at_path(path: FamilyPath<T>, bundle: U) -> Self {
+        Self::at_path(Action::Create, path, bundle)
+    }
+    /// Create the event of a recorded event, with its decoded bundle.
+    pub fn from_recorded(recorded: &RecordedEvent<T>, bundle: U) -> Self {
+        Self {
+            action: recorded.action.clone(),
+            parent_identifier: recorded.parent_identifier.clone(),
+            path: recorded.path.clone(),
+            self_identifier: recorded.self_identifier.clone(),
+            bundle,
+        }
+    }
+
+    /// Create the entity that the path leads to, creating its missing ancestors from the `AncestorBundles` first.
+    pub fn create_with_ancestors(path: FamilyPath<T>, bundle: U) -> Self {
+        Self::at_path(Action::CreateWithAncestors, path, bundle)
+    }
+    pub fn create_or_modify_at_path(path: FamilyPath<T>, bundle: U) -> Self {
+        Self::at_path(Action::CreateOrModify, path, bundle)
+    }
+    pub fn update_at_path(path: FamilyPath<T>, bundle: U) -> Self {
+        Self::at_path(Action::Update, path, bundle)
+    }
+    pub fn delete_at_path(path: FamilyPath<T>, bundle: U) -> Self {
+        Self::at_path(Action::Delete, path, bundle)
+    }
+    pub fn clear_at_path(path: FamilyPath<T>, bundle: U) -> Self {
+        Self::at_path(Action::Clear, path, bundle)
+    }
+    pub fn touch_at_path(path: FamilyPath<T>, bundle: U) -> Self {
+        Self::at_path(Action::Touch, path, bundle)
+    }
 }
 
 /// History of the action that has been performed
 #[derive(PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct History<T> {
     pub action: Action,
     pub parent_identifier: Identifier<T>,
     pub child_identifier: Option<Identifier<T>>,
-    pub result: Result<(), ()>,
+    pub result: Result<(), FamilyError>,
+    /// Generation of the entity that the action was performed on.
+    pub generation: u32,
+    /// Position of the history in the lineage, that keeps increasing when histories are removed.
+    pub sequence: u64,
+    /// Elapsed `Time` when the history was added to the lineage.
+    pub timestamp: Duration,
 }
 
 /// Lineage of the actions that have been performed
 #[derive(Resource, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Lineage<T> {
     pub histories: Vec<History<T>>,
+    /// Sequence of the next history.
+    pub(crate) next_sequence: u64,
+    /// Elapsed `Time` of the current frame.
+    pub(crate) elapsed: Duration,
 }
 
 impl<T> Lineage<T>
 where
     T: Clone + PartialEq,
 {
-    /// Add a history to the lineage.
-    pub fn add_history(&mut self, history: History<T>) {
+    /// Add a history to the lineage, with the next sequence and the elapsed time.
+    pub fn add_history(&mut self, mut history: History<T>) {
+        history.sequence = self.next_sequence;
+        history.timestamp = self.elapsed;
+        self.next_sequence += 1;
         self.histories.push(history);
     }
 
     /// Remove a history from the lineage.
+    ///
+    /// The histories are matched by their action, identifiers and result, as the generation, sequence and timestamp are only known once they are added.
     pub fn remove_history(&mut self, history: History<T>) {
-        self.histories.retain(|h| h != &history);
+        self.histories.retain(|h| {
+            h.action != history.action || h.parent_identifier != history.parent_identifier || h.child_identifier != history.child_identifier || h.result != history.result
+        });
     }
 
     /// Get the history by the parent identifier.
@@ -223,7 +518,7 @@ where
                 histories.push(history);
             }
         }
-        return histories;
+        histories
     }
 
     /// Get the history by the child identifier.
@@ -236,21 +531,21 @@ where
                 }
             }
         }
-        return histories;
+        histories
     }
 
     /// Get the result by the parent identifier.
-    pub fn get_result_from_parent_identifier(&self, parent_identifier: &Identifier<T>) -> Result<(), ()> {
+    pub fn get_result_from_parent_identifier(&self, parent_identifier: &Identifier<T>) -> Result<(), FamilyError> {
         for history in &self.histories {
             if &history.parent_identifier == parent_identifier {
                 return history.result;
             }
         }
-        return Err(());
+        Err(FamilyError::NoHistory)
     }
 
     /// Get the result by the child identifier.
-    pub fn get_result_from_child_identifier(&self, child_identifier: &Identifier<T>) -> Result<(), ()> {
+    pub fn get_result_from_child_identifier(&self, child_identifier: &Identifier<T>) -> Result<(), FamilyError> {
         for history in &self.histories {
             if let Some(identifier) = &history.child_identifier {
                 if identifier == child_identifier {
@@ -258,7 +553,7 @@ where
                 }
             }
         }
-        return Err(());
+        Err(FamilyError::NoHistory)
     }
 
     /// Clear the history.
@@ -278,32 +573,62 @@ where
 
     /// Pop the history.
     pub fn pop(&mut self) -> Option<History<T>> {
-        return self.histories.pop();
+        self.histories.pop()
     }
 }
 
 impl<T> History<T> {
+    /// Create a new history of an entity, which is a child history if it has a parent.
+    pub fn new(action: Action, parent_identifier: Option<Identifier<T>>, self_identifier: Identifier<T>, result: Result<(), FamilyError>) -> Self {
+        match parent_identifier {
+            Some(parent_identifier) => Self::new_child_history(action, parent_identifier, self_identifier, result),
+            None => Self::new_parent_history(action, self_identifier, result),
+        }
+    }
+
+    /// Set the generation of the entity that the action was performed on.
+    pub fn with_generation(mut self, generation: u32) -> Self {
+        self.generation = generation;
+        self
+    }
+
     /// Create a new parent history.
-    pub fn new_parent_history(action: Action, parent_identifier: Identifier<T>, result: Result<(), ()>) -> Self {
+    pub fn new_parent_history(action: Action, parent_identifier: Identifier<T>, result: Result<(), FamilyError>) -> Self {
         Self {
             action,
             parent_identifier,
             child_identifier: None,
             result,
+            generation: 0,
+            sequence: 0,
+            timestamp: Duration::ZERO,
         }
     }
 
     /// Create a new child history.
-    pub fn new_child_history(action: Action, parent_identifier: Identifier<T>, child_identifier: Identifier<T>, result: Result<(), ()>) -> Self {
+    pub fn new_child_history(action: Action, parent_identifier: Identifier<T>, child_identifier: Identifier<T>, result: Result<(), FamilyError>) -> Self {
         Self {
             action,
             parent_identifier,
             child_identifier: Some(child_identifier),
             result,
+            generation: 0,
+            sequence: 0,
+            timestamp: Duration::ZERO,
         }
     }
 }
 
+/// Keeps the elapsed time of the lineage up to date, to timestamp its histories
+pub fn advance_lineage_time<T>(mut lineage: ResMut<Lineage<T>>, time: Option<Res<Time>>)
+where
+    T: Send + Sync + 'static,
+{
+    if let Some(time) = time {
+        lineage.bypass_change_detection().elapsed = time.elapsed();
+    }
+}
+
 /// Get the entity by the identifier.
 pub fn get_entity_by_identifier<U>(queries: &Query<(Entity, &Identifier<U>), With<Identifier<U>>>, identifier: &Identifier<U>) -> Option<Entity>
 where
@@ -314,59 +639,121 @@ where
             return Some(entity);
         }
     }
-    return None;
+    None
 }
 
-pub fn cud_bundle<U, V>(mut commands: Commands, queries: Query<(Entity, &Identifier<V>), With<Identifier<V>>>, mut events: EventReader<CudEvent<U, V>>, mut lineage: ResMut<Lineage<V>>)
+/// Spawn an entity of the bundle `U` with its identifier and generation, and a `BiologicalClock` if the entity ages.
+fn spawn_member<U, V>(commands: &mut Commands, bundle: impl Bundle, identifier: Identifier<V>, ages: bool, generation: u32) -> Entity
 where
+    U: Bundle,
+    V: Send + Sync + 'static,
+{
+    let mut entity_commands = commands.spawn((bundle, identifier, FamilyMember::<U>::default(), Generation(generation)));
+    if ages {
+        entity_commands.insert(BiologicalClock::default());
+    }
+    entity_commands.id()
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn cud_bundle<U, V>(
+    mut commands: Commands,
+    family: FamilyQuery<V>,
+    mut events: EventReader<CudEvent<U, V>>,
+    mut lineage: ResMut<Lineage<V>>,
+    mut population: Population<U, V>,
+    clock_insertion: Option<Res<ClockInsertion<U>>>,
+    mut generations: ResMut<Generations<V>>,
+    mut ancestors: Ancestors<V>,
+    mut recorder: Recorder<U, V>,
+) where
     U: Bundle + Clone,
     V: Clone + std::fmt::Debug + PartialEq + Send + Sync + 'static,
 {
+    let ages = clock_insertion.is_none_or(|clock_insertion| clock_insertion.enabled);
+    population.begin();
+    ancestors.begin();
     for event in events.read() {
         let action = event.get_action();
         let self_identifier = event.get_self_identifier();
+        let mut generation = generations.get(event.get_parent_identifier(), self_identifier);
+        recorder.begin(event, &mut commands);
         match event.get_parent_identifier() {
             // if there is a parent identifier
             Some(parent_identifier) => {
-                match get_entity_by_identifier(&queries, parent_identifier) {
+                let parent_path = event.get_path().as_ref().and_then(FamilyPath::parent);
+                let parent_entity = match &parent_path {
+                    Some(parent_path) if *action == Action::CreateWithAncestors => {
+                        let (existing, len) = ancestors.find(parent_path, &family);
+                        if len < parent_path.identifiers().len() {
+                            // the ancestors and the entity are checked together, so that nothing is created when one of them cannot be
+                            let members = ancestors.members::<U>(parent_path, len);
+                            if let Err(error) = population.make_room_for_ancestors(existing, &members, &family, &mut commands, &mut lineage) {
+                                warn!("Entity {:?} cannot be created with its ancestors: {}.", self_identifier, error);
+                                lineage.add_history(event.to_history(Err(error)).with_generation(generation));
+                                continue;
+                            }
+                        }
+                        Some(ancestors.spawn(parent_path, (existing, len), ages, &mut population, &mut commands, &mut lineage, &mut generations))
+                    }
+                    Some(parent_path) => family.get_by_path(parent_path),
+                    None => family.get(parent_identifier),
+                };
+                match parent_entity {
                     // if parent is found
                     Some(parent_entity) => {
-                        match get_entity_by_identifier(&queries, self_identifier) {
+                        let child_entity = match event.get_path() {
+                            Some(_) => family.children_of(parent_entity).find(|(_, id)| *id == self_identifier).map(|(entity, _)| entity),
+                            None => family.get(self_identifier),
+                        };
+                        match child_entity {
                             // if child is found
                             Some(child_entity) => match action {
-                                Action::CreateOrModify | Action::Update => {
+                                Action::CreateOrModify | Action::Update | Action::Reincarnate(_) => {
                                     commands.entity(child_entity).despawn_recursive();
-                                    let child = commands.spawn((event.get_bundle(), self_identifier.clone(), BiologicalClock::default())).id();
+                                    generation = generations.next(event.get_parent_identifier(), self_identifier);
+                                    let child = spawn_member::<U, V>(&mut commands, event.get_bundle(), self_identifier.clone(), ages, generation);
                                     commands.entity(parent_entity).add_child(child);
                                     debug!("Child entity {:?} under parent entity {:?} is updated.", self_identifier, parent_entity);
-                                    lineage.add_history(event.to_history(Ok(())));
+                                    lineage.add_history(event.to_history(Ok(())).with_generation(generation));
                                 }
                                 Action::Delete => {
                                     commands.entity(child_entity).despawn_recursive();
                                     debug!("Child entity {:?} under parent entity {:?} is deleted.", self_identifier, parent_entity);
-                                    lineage.add_history(event.to_history(Ok(())));
+                                    lineage.add_history(event.to_history(Ok(())).with_generation(generation));
                                 }
                                 Action::Clear => {
                                     commands.entity(child_entity).despawn_descendants();
                                     debug!("Child entity {:?}'s childrens are cleared.", self_identifier);
-                                    lineage.add_history(event.to_history(Ok(())));
+                                    lineage.add_history(event.to_history(Ok(())).with_generation(generation));
+                                }
+                                Action::Touch => {
+                                    commands.entity(child_entity).queue(renew_biological_clock);
+                                    debug!("Child entity {:?} under parent entity {:?} is touched.", self_identifier, parent_entity);
+                                    lineage.add_history(event.to_history(Ok(())).with_generation(generation));
                                 }
                                 _ => {
                                     warn!("Parent {:?} already consist of child entity {:?}.", parent_identifier, self_identifier);
-                                    lineage.add_history(event.to_history(Err(())));
+                                    lineage.add_history(event.to_history(Err(FamilyError::AlreadyExists)).with_generation(generation));
                                 }
                             },
                             // if child not found
                             None => match action {
-                                Action::Create | Action::CreateOrModify => {
-                                    let child = commands.spawn((event.get_bundle(), self_identifier.clone(), BiologicalClock::default())).id();
+                                Action::Create | Action::CreateOrModify | Action::CreateWithAncestors | Action::Reincarnate(_) => {
+                                    if let Err(error) = population.make_room(Some(parent_entity), &family, &mut commands, &mut lineage) {
+                                        warn!("Child entity {:?} cannot be created under parent entity {:?}: {}.", self_identifier, parent_entity, error);
+                                        lineage.add_history(event.to_history(Err(error)).with_generation(generation));
+                                        continue;
+                                    }
+                                    generation = generations.next(event.get_parent_identifier(), self_identifier);
+                                    let child = spawn_member::<U, V>(&mut commands, event.get_bundle(), self_identifier.clone(), ages, generation);
                                     commands.entity(parent_entity).add_child(child);
                                     debug!("Child entity {:?} created under parent entity {:?}.", self_identifier, parent_entity,);
-                                    lineage.add_history(event.to_history(Ok(())));
+                                    lineage.add_history(event.to_history(Ok(())).with_generation(generation));
                                 }
                                 _ => {
                                     warn!("Parent entity {:?} does not have child entity {:?}.", parent_entity, self_identifier);
-                                    lineage.add_history(event.to_history(Err(())));
+                                    lineage.add_history(event.to_history(Err(FamilyError::NotFound)).with_generation(generation));
                                 }
                             },
                         }
@@ -374,96 +761,209 @@ where
                     // if parent entity not found
                     None => {
                         warn!("Parent entity {:?} does not exist.", parent_identifier);
-                        lineage.add_history(event.to_history(Err(())));
+                        lineage.add_history(event.to_history(Err(FamilyError::ParentNotFound)).with_generation(generation));
                         continue;
                     }
                 }
             }
             // if there is no parent identifier, spawn as a parent entity
             None => {
-                match get_entity_by_identifier(&queries, self_identifier) {
+                let entity = match event.get_path() {
+                    Some(path) => family.get_by_path(path),
+                    None => family.get(self_identifier),
+                };
+                match entity {
                     // if identifier is found
                     Some(entity) => match action {
-                        Action::CreateOrModify | Action::Update => {
+                        Action::CreateOrModify | Action::Update | Action::Reincarnate(_) => {
                             debug!("Parent entity {:?} updated.", self_identifier);
                             commands.entity(entity).despawn_recursive();
-                            commands.spawn((event.get_bundle(), self_identifier.clone(), BiologicalClock::default()));
-                            lineage.add_history(event.to_history(Ok(())));
+                            generation = generations.next(event.get_parent_identifier(), self_identifier);
+                            spawn_member::<U, V>(&mut commands, event.get_bundle(), self_identifier.clone(), ages, generation);
+                            lineage.add_history(event.to_history(Ok(())).with_generation(generation));
                         }
                         Action::Delete => {
                             commands.entity(entity).despawn_recursive();
                             debug!("Parent entity {:?} deleted.", self_identifier);
-                            lineage.add_history(event.to_history(Ok(())));
+                            lineage.add_history(event.to_history(Ok(())).with_generation(generation));
                         }
                         Action::Clear => {
                             commands.entity(entity).despawn_descendants();
                             debug!("Parent entity's {:?} childrens cleared.", self_identifier);
-                            lineage.add_history(event.to_history(Ok(())));
+                            lineage.add_history(event.to_history(Ok(())).with_generation(generation));
+                        }
+                        Action::Touch => {
+                            commands.entity(entity).queue(renew_biological_clock);
+                            debug!("Parent entity {:?} touched.", self_identifier);
+                            lineage.add_history(event.to_history(Ok(())).with_generation(generation));
                         }
                         _ => {
                             warn!("Parent entity {:?} already exists.", self_identifier);
-                            lineage.add_history(event.to_history(Err(())));
+                            lineage.add_history(event.to_history(Err(FamilyError::AlreadyExists)).with_generation(generation));
                         }
                     },
                     // if identifier not found
                     None => match action {
-                        Action::Create | Action::CreateOrModify => {
+                        Action::Create | Action::CreateOrModify | Action::CreateWithAncestors | Action::Reincarnate(_) => {
+                            if let Err(error) = population.make_room(None, &family, &mut commands, &mut lineage) {
+                                warn!("Parent entity {:?} cannot be created: {}.", self_identifier, error);
+                                lineage.add_history(event.to_history(Err(error)).with_generation(generation));
+                                continue;
+                            }
                             debug!("Parent entity {:?} created.", self_identifier);
-                            commands.spawn((event.get_bundle(), self_identifier.clone(), BiologicalClock::default()));
-                            lineage.add_history(event.to_history(Ok(())));
+                            generation = generations.next(event.get_parent_identifier(), self_identifier);
+                            spawn_member::<U, V>(&mut commands, event.get_bundle(), self_identifier.clone(), ages, generation);
+                            lineage.add_history(event.to_history(Ok(())).with_generation(generation));
                         }
                         _ => {
                             warn!("Parent entity {:?} does not exist.", self_identifier);
-                            lineage.add_history(event.to_history(Err(())));
+                            lineage.add_history(event.to_history(Err(FamilyError::NotFound)).with_generation(generation));
                         }
                     },
                 }
             }
         }
+        // the history of the event is always the last one that is added
+        if lineage.histories.last().is_some_and(|history| history.result.is_ok()) {
+            recorder.record(event, &mut commands);
+        }
     }
 }
 
-/// Acts like a garbage collector to remove entities that have exceeded their own lifetime
-pub fn refresh_by_own_lifetime<T>(mut commands: Commands, time: Res<Time>, mut queries: Query<(Entity, &mut BiologicalClock, &T), (With<BiologicalClock>, With<T>)>)
-where
-    T: Component + BiologicalTrait,
-{
-    for (entity, mut bioglical_clock, component) in queries.iter_mut() {
-        if component.get_lifetime() < bioglical_clock.lifetime.elapsed() {
-            // Dark, but kills of all the children if the parent dies
-            commands.entity(entity).despawn_recursive();
-            debug!("Entity died");
-        } else {
-            bioglical_clock.lifetime.tick(time.delta());
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{app, send, Node};
+
+    #[test]
+    fn root_path_does_not_match_a_child() {
+        let mut app = app();
+        send(&mut app, CudEvent::create_parent("P".to_string(), Node::default()));
+        send(&mut app, CudEvent::create_child("P".to_string(), "A".to_string(), Node::default()));
+        send(&mut app, CudEvent::delete_at_path(FamilyPath::root("A".to_string()), Node::default()));
+
+        let lineage = app.world().resource::<Lineage<String>>();
+        assert_eq!(lineage.histories.last().unwrap().result, Err(FamilyError::NotFound));
+        assert_eq!(app.world_mut().query::<&Identifier<String>>().iter(app.world()).count(), 2);
+    }
+
+    #[test]
+    fn remove_history_ignores_generation_sequence_and_timestamp() {
+        let mut app = app();
+        send(&mut app, CudEvent::create_parent("P".to_string(), Node::default()));
+        send(&mut app, CudEvent::create_child("P".to_string(), "A".to_string(), Node::default()));
+
+        let mut lineage = app.world_mut().resource_mut::<Lineage<String>>();
+        lineage.remove_history(History::new_child_history(Action::Create, Identifier("P".to_string()), Identifier("A".to_string()), Ok(())));
+        assert_eq!(lineage.histories.len(), 1);
+        assert_eq!(lineage.histories[0].child_identifier, None);
+    }
+
+    #[test]
+    fn retained_generations_keep_counting() {
+        let mut generations = Generations::<String>::default();
+        let (parent, a, b) = (Some(Identifier("P".to_string())), Identifier("A".to_string()), Identifier("B".to_string()));
+        generations.next(&parent, &a);
+        generations.next(&parent, &a);
+        generations.next(&None, &b);
+        assert_eq!(generations.len(), 2);
+
+        generations.retain(|parent_identifier, _, _| parent_identifier.is_some());
+        assert_eq!(generations.len(), 1);
+        assert_eq!(generations.get(&None, &b), 0);
+        assert!(generations.is_stale(&parent, &a, Generation(1)));
+        assert_eq!(generations.next(&parent, &a), 3);
+        assert_eq!(generations.next(&None, &b), 1);
     }
 }
 
-/// Acts like a garbage collector to remove entities that have exceeded their parent's lifetime
-pub fn refresh_by_parent_lifetime<T, U>(
-    mut commands: Commands,
-    time: Res<Time>,
-    parent_queries: Query<&T, With<T>>,
-    mut child_queries: Query<(&Parent, Entity, &mut BiologicalClock), (With<BiologicalClock>, With<U>)>,
-) where
-    T: Component + BiologicalTrait,
-    U: Component,
-{
-    for (parent, child_entity, mut child_bioglical_clock) in child_queries.iter_mut() {
-        match parent_queries.get(**parent) {
-            Ok(parent_component) => {
-                if parent_component.get_lifetime() < child_bioglical_clock.lifetime.elapsed() {
-                    // Dark, but kills of all the children if the parent dies
-                    commands.entity(child_entity).despawn_recursive();
-                    debug!("Entity died");
-                } else {
-                    child_bioglical_clock.lifetime.tick(time.delta());
-                }
-            }
-            Err(_) => {
-                warn!("Parent entity does not exist");
-                continue;
-            }
+#[cfg(all(test, feature = "serde"))]
+mod serde_tests {
+    use super::*;
+    use serde::de::DeserializeOwned;
+    use serde::Serialize;
+
+    /// Check that the value is the same after a round trip through JSON and RON.
+    fn round_trip<S>(value: &S)
+    where
+        S: Serialize + DeserializeOwned + PartialEq,
+    {
+        let json = serde_json::to_string(value).unwrap();
+        assert!(serde_json::from_str::<S>(&json).unwrap() == *value, "JSON {}", json);
+        let ron = ron::to_string(value).unwrap();
+        assert!(ron::from_str::<S>(&ron).unwrap() == *value, "RON {}", ron);
+    }
+
+    #[test]
+    fn identifiers_round_trip() {
+        round_trip(&Identifier("Building".to_string()));
+        round_trip(&Identifier(42_u64));
+    }
+
+    #[test]
+    fn actions_round_trip() {
+        for action in [
+            Action::Create,
+            Action::CreateOrModify,
+            Action::Update,
+            Action::Delete,
+            Action::Clear,
+            Action::Touch,
+            Action::Expire,
+            Action::Reincarnate(3),
+            Action::Evict,
+            Action::CreateWithAncestors,
+            Action::Undo,
+            Action::Redo,
+        ] {
+            round_trip(&action);
+        }
+    }
+
+    #[test]
+    fn family_errors_round_trip() {
+        for error in [
+            FamilyError::ParentNotFound,
+            FamilyError::NotFound,
+            FamilyError::AlreadyExists,
+            FamilyError::PopulationCapReached,
+            FamilyError::NoHistory,
+            FamilyError::MaxDepthExceeded,
+            FamilyError::MaxChildrenExceeded,
+            FamilyError::MaxMembersExceeded,
+        ] {
+            round_trip(&error);
+            round_trip::<Result<(), FamilyError>>(&Err(error));
+        }
+    }
+
+    #[test]
+    fn histories_round_trip() {
+        let mut history = History::new_child_history(Action::Update, Identifier("Building".to_string()), Identifier("Room".to_string()), Err(FamilyError::NotFound)).with_generation(2);
+        history.sequence = 7;
+        history.timestamp = Duration::from_millis(1500);
+        round_trip(&history);
+        round_trip(&History::new(Action::Reincarnate(4), None, Identifier("Npc".to_string()), Ok(())).with_generation(4));
+    }
+
+    #[test]
+    fn lineages_round_trip() {
+        let (building, room) = (Identifier("Building".to_string()), Identifier("Room".to_string()));
+        let mut lineage = Lineage::<String> {
+            elapsed: Duration::from_secs(3),
+            ..Default::default()
+        };
+        lineage.add_history(History::new(Action::Create, None, building.clone(), Ok(())).with_generation(1));
+        lineage.add_history(History::new(Action::Create, Some(building.clone()), room, Err(FamilyError::MaxChildrenExceeded)));
+        lineage.pop();
+        lineage.add_history(History::new(Action::Delete, None, building, Ok(())).with_generation(1));
+
+        let json: Lineage<String> = serde_json::from_str(&serde_json::to_string(&lineage).unwrap()).unwrap();
+        let ron: Lineage<String> = ron::from_str(&ron::to_string(&lineage).unwrap()).unwrap();
+        for reloaded in [json, ron] {
+            assert!(reloaded.histories == lineage.histories);
+            assert_eq!(reloaded.next_sequence, 3);
+            assert_eq!(reloaded.elapsed, Duration::from_secs(3));
         }
     }
 }
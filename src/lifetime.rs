@@ -0,0 +1,644 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
+use bevy_ecs::schedule::{InternedScheduleLabel, ScheduleLabel};
+use bevy_hierarchy::prelude::*;
+use bevy_log::prelude::*;
+use bevy_time::prelude::*;
+
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{expire_by_deadline, schedule_lifetime_deadlines, Action, ClockAnchor, FamilyQuery, Generation, History, Identifier, LifetimeDeadlines, Lineage};
+
+/// Trait for components that have a lifetime.
+///
+/// Implement either `get_lifetime` for a duration-based life, or `get_lifespan` for any other kind of life.
+pub trait BiologicalTrait {
+    /// Duration that the entity lives for.
+    fn get_lifetime(&self) -> std::time::Duration {
+        Duration::MAX
+    }
+
+    /// Length of the entity's life, defaults to the duration of `get_lifetime`.
+    fn get_lifespan(&self) -> Lifespan {
+        Lifespan::Duration(self.get_lifetime())
+    }
+
+    /// Condition that ends the entity's life when the lifespan is `Lifespan::UntilCondition`.
+    fn is_expired(&self) -> bool {
+        false
+    }
+}
+
+/// Length of an entity's life.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Lifespan {
+    /// Lives for a duration of the lifetime clock.
+    Duration(Duration),
+    /// Lives for a number of updates of the lifetime system.
+    Frames(u64),
+    /// Lives for a number of fixed timesteps.
+    Ticks(u64),
+    /// Lives until `BiologicalTrait::is_expired` returns true.
+    UntilCondition,
+}
+
+impl Lifespan {
+    /// Check if a clock has outlived the lifespan.
+    ///
+    /// `UntilCondition` is never outlived by a clock, as it only depends on the component.
+    pub fn is_exceeded_by(&self, clock: &BiologicalClock) -> bool {
+        match self {
+            Lifespan::Duration(duration) => *duration < clock.age(),
+            Lifespan::Frames(frames) => *frames <= clock.lived_frames(),
+            Lifespan::Ticks(ticks) => *ticks <= clock.ticks,
+            Lifespan::UntilCondition => false,
+        }
+    }
+
+    /// Get the fraction of the lifespan that a clock has lived through.
+    ///
+    /// Returns `None` for `UntilCondition`, as it has no length.
+    pub fn progress_of(&self, clock: &BiologicalClock) -> Option<f32> {
+        match self {
+            Lifespan::Duration(duration) => Some(clock.age().as_secs_f32() / duration.as_secs_f32()),
+            Lifespan::Frames(frames) => Some(clock.lived_frames() as f32 / *frames as f32),
+            Lifespan::Ticks(ticks) => Some(clock.ticks as f32 / *ticks as f32),
+            Lifespan::UntilCondition => None,
+        }
+    }
+}
+
+/// A component that represents the entity's lifetime.
+///
+/// With the deadline backend, the clock is not ticked: its age is the time that has passed on the deadline clock since it was scheduled.
+/// Read the age with `age` and `lived_frames`, which cover both backends.
+#[derive(Component, Default)]
+pub struct BiologicalClock {
+    pub lifetime: bevy_time::Stopwatch,
+    /// Number of updates of the lifetime system that the entity has lived through.
+    pub frames: u64,
+    /// Number of fixed timesteps that the entity has lived through.
+    pub ticks: u64,
+    /// Point of the deadline clock that the clock has been aging from since it was scheduled, with the deadline backend.
+    pub(crate) anchor: Option<ClockAnchor>,
+}
+
+impl BiologicalClock {
+    /// Age the clock by one update of the lifetime system.
+    pub fn tick(&mut self, delta: Duration) {
+        self.lifetime.tick(delta);
+        if !self.lifetime.is_paused() {
+            self.frames += 1;
+        }
+    }
+
+    /// Age the clock by one fixed timestep.
+    pub fn tick_fixed(&mut self) {
+        if !self.lifetime.is_paused() {
+            self.ticks += 1;
+        }
+    }
+
+    /// Reset the clock as if the entity was just born.
+    pub fn reset(&mut self) {
+        self.lifetime.reset();
+        self.frames = 0;
+        self.ticks = 0;
+        self.anchor = None;
+    }
+
+    /// Get the time that the entity has lived through.
+    pub fn age(&self) -> Duration {
+        self.lifetime.elapsed() + self.anchor.as_ref().map_or(Duration::ZERO, ClockAnchor::elapsed)
+    }
+
+    /// Get the number of updates of the lifetime system that the entity has lived through.
+    pub fn lived_frames(&self) -> u64 {
+        self.frames + self.anchor.as_ref().map_or(0, ClockAnchor::frames)
+    }
+
+    /// Copy the clock at its current age, without the deadline clock that it is aging from.
+    pub fn frozen(&self) -> Self {
+        let mut lifetime = self.lifetime.clone();
+        lifetime.set_elapsed(self.age());
+        Self {
+            lifetime,
+            frames: self.lived_frames(),
+            ticks: self.ticks,
+            anchor: None,
+        }
+    }
+}
+
+/// A component that keeps the entity alive for as long as it is touched within the timeout.
+///
+/// Touching the entity with `CudEvent::touch_*` or updating it with `CudEvent::update_*` renews its `BiologicalClock`,
+/// so its lifetime is the time since it was last touched. Add a `LifetimePlugin::<Lease>` to expire the leases.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Lease {
+    pub timeout: Duration,
+}
+
+impl Lease {
+    pub fn new(timeout: Duration) -> Self {
+        Self { timeout }
+    }
+}
+
+impl BiologicalTrait for Lease {
+    fn get_lifetime(&self) -> Duration {
+        self.timeout
+    }
+}
+
+/// Reset the `BiologicalClock` of the entity, if it has one.
+pub(crate) fn renew_biological_clock(mut entity: EntityWorldMut) {
+    if let Some(mut biological_clock) = entity.get_mut::<BiologicalClock>() {
+        biological_clock.reset();
+    }
+}
+
+/// Resource that decides if `cud_bundle` inserts a `BiologicalClock` on the entities of the bundle `U`.
+///
+/// Without this resource, every entity gets a clock. Components registered with a `LifetimePlugin` require a clock,
+/// so they still get one when the insertion is disabled for their bundle.
+#[derive(Resource)]
+pub struct ClockInsertion<U> {
+    pub enabled: bool,
+    _marker: PhantomData<U>,
+}
+
+impl<U> ClockInsertion<U> {
+    /// Insert a clock on every entity of the bundle.
+    pub fn enabled() -> Self {
+        Self {
+            enabled: true,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Do not insert a clock on the entities of the bundle.
+    pub fn disabled() -> Self {
+        Self {
+            enabled: false,
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// Event that is used to attach or detach the `BiologicalClock` of an existing entity
+#[derive(Event)]
+pub struct AgingEvent<T> {
+    identifier: Identifier<T>,
+    attach: bool,
+}
+
+impl<T> AgingEvent<T> {
+    pub fn get_identifier(&self) -> &Identifier<T> {
+        &self.identifier
+    }
+
+    pub fn is_attach(&self) -> bool {
+        self.attach
+    }
+
+    pub fn attach(identifier: T) -> Self {
+        Self {
+            identifier: Identifier(identifier),
+            attach: true,
+        }
+    }
+    pub fn detach(identifier: T) -> Self {
+        Self {
+            identifier: Identifier(identifier),
+            attach: false,
+        }
+    }
+}
+
+/// Attaches a new `BiologicalClock` to, or detaches the clock from, the entities of the aging events
+pub fn attach_or_detach_aging<T>(mut commands: Commands, family: FamilyQuery<T>, mut events: EventReader<AgingEvent<T>>)
+where
+    T: std::fmt::Debug + PartialEq + Send + Sync + 'static,
+{
+    for event in events.read() {
+        let Some(entity) = family.get(event.get_identifier()) else {
+            warn!("Entity {:?} does not exist.", event.get_identifier());
+            continue;
+        };
+        if event.is_attach() {
+            commands.entity(entity).insert(BiologicalClock::default());
+            debug!("Entity {:?} starts aging.", event.get_identifier());
+        } else {
+            commands.entity(entity).remove::<BiologicalClock>();
+            debug!("Entity {:?} stops aging.", event.get_identifier());
+        }
+    }
+}
+
+/// Source of time that is used to age the entities.
+///
+/// Implemented for every `Time<T>` (`Time`, `Time<Virtual>`, `Time<Real>` and `Time<Fixed>`) and for the `ManualClock`.
+/// Implement it for your own resource to age entities with a custom clock.
+pub trait LifetimeClock: Resource {
+    /// Time that has passed since the last update of the clock.
+    fn delta(&self) -> Duration;
+
+    /// Add the systems that the clock needs to the app, called by every plugin that uses the clock.
+    fn build(_app: &mut App) {}
+}
+
+impl<C> LifetimeClock for Time<C>
+where
+    C: Default + Send + Sync + 'static,
+{
+    fn delta(&self) -> Duration {
+        Time::delta(self)
+    }
+}
+
+/// A clock that only moves when it is stepped.
+///
+/// Useful for deterministic tests, where the lifetime systems should not depend on the wall time.
+/// The steps are applied once, in `First` of the next update, so every step ages the entities by exactly its delta.
+#[derive(Resource, Default, Clone, Debug)]
+pub struct ManualClock {
+    pending: Duration,
+    delta: Duration,
+    elapsed: Duration,
+}
+
+impl ManualClock {
+    /// Step the clock forward on the next update.
+    pub fn step(&mut self, delta: Duration) {
+        self.pending += delta;
+        self.elapsed += delta;
+    }
+
+    /// Get the total time that the clock has been stepped.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+}
+
+impl LifetimeClock for ManualClock {
+    fn delta(&self) -> Duration {
+        self.delta
+    }
+
+    fn build(app: &mut App) {
+        if !app.is_plugin_added::<ManualClockPlugin>() {
+            app.add_plugins(ManualClockPlugin);
+        }
+    }
+}
+
+/// Plugin that applies the steps of the `ManualClock`, added by the plugins that use it
+struct ManualClockPlugin;
+
+impl Plugin for ManualClockPlugin {
+    /// Build the plugin
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ManualClock>();
+        app.add_systems(First, advance_manual_clock);
+    }
+}
+
+/// Moves the pending steps of the manual clock into the delta of this update
+pub fn advance_manual_clock(mut clock: ResMut<ManualClock>) {
+    clock.delta = std::mem::take(&mut clock.pending);
+}
+
+/// Event that is triggered on an entity right before it is despawned for exceeding its lifetime.
+///
+/// Observers of this event can still read the components of the entity.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct Expired;
+
+/// System set of the systems that age and expire the entities.
+#[derive(Debug, PartialEq, Eq, Clone, Hash, SystemSet)]
+pub struct LifetimeSystem;
+
+/// Backend that decides when the entities have exceeded their lifetime.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LifetimeBackend {
+    /// Tick every `BiologicalClock` on every update and compare it against the lifespan.
+    #[default]
+    Clock,
+    /// Keep the deadlines of the entities in a priority queue and only visit the entities that expire.
+    ///
+    /// Only supports `Lifespan::Duration` and `Lifespan::Frames`. The clocks are not ticked, so `BiologicalClock::lifetime` and
+    /// `BiologicalClock::frames` stay at the age that the clock was scheduled at, while `BiologicalClock::age` and `BiologicalClock::lived_frames` keep up.
+    Deadline,
+}
+
+/// Plugin that ages the entities of type `T` with the clock `C`.
+///
+/// By default, the entities are aged with `Time` in the `Update` schedule.
+pub struct LifetimePlugin<T, C = Time> {
+    schedule: InternedScheduleLabel,
+    backend: LifetimeBackend,
+    conditions: Vec<ExpiryPredicate>,
+    _marker: PhantomData<(T, C)>,
+}
+
+impl<T, C> Default for LifetimePlugin<T, C> {
+    fn default() -> Self {
+        Self::new(Update)
+    }
+}
+
+impl<T, C> LifetimePlugin<T, C> {
+    /// Create the plugin that runs the lifetime system in the given schedule.
+    pub fn new(schedule: impl ScheduleLabel) -> Self {
+        Self {
+            schedule: schedule.intern(),
+            backend: LifetimeBackend::default(),
+            conditions: Vec::new(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Select the backend that expires the entities.
+    pub fn with_backend(mut self, backend: LifetimeBackend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Also expire the entities once the predicate over their component becomes true.
+    pub fn with_expiry_condition(mut self, predicate: impl Fn(&T) -> bool + Send + Sync + 'static) -> Self
+    where
+        T: Component,
+    {
+        self.conditions.push(Arc::new(move |entity: EntityRef| entity.get::<T>().is_some_and(&predicate)));
+        self
+    }
+
+    /// Also expire the entities once the predicate over the entity becomes true, e.g. when it has no children left.
+    pub fn with_entity_expiry_condition(mut self, predicate: impl Fn(EntityRef) -> bool + Send + Sync + 'static) -> Self {
+        self.conditions.push(Arc::new(predicate));
+        self
+    }
+}
+
+impl<T, C> Plugin for LifetimePlugin<T, C>
+where
+    T: Component + BiologicalTrait,
+    C: LifetimeClock,
+{
+    /// Build the plugin
+    fn build(&self, app: &mut App) {
+        // the component may already require a clock through another plugin or its own definition
+        let _ = app.try_register_required_components::<T, BiologicalClock>();
+        C::build(app);
+        match self.backend {
+            LifetimeBackend::Clock => {
+                app.add_systems(self.schedule, refresh_by_own_lifetime_with_clock::<T, C>.in_set(LifetimeSystem));
+                app.add_systems(FixedUpdate, tick_fixed_biological_clock::<T>.in_set(LifetimeSystem));
+            }
+            LifetimeBackend::Deadline => {
+                app.init_resource::<LifetimeDeadlines<T>>();
+                app.add_systems(self.schedule, (schedule_lifetime_deadlines::<T>, expire_by_deadline::<T, C>).chain().in_set(LifetimeSystem));
+            }
+        }
+        if !self.conditions.is_empty() {
+            app.init_resource::<ExpiryCondition<T>>();
+            app.world_mut().resource_mut::<ExpiryCondition<T>>().predicates.extend(self.conditions.iter().cloned());
+            app.add_systems(self.schedule, expire_by_condition::<T>.in_set(LifetimeSystem));
+        }
+    }
+}
+
+/// Counts the fixed timesteps that the entities with `T` have lived through, for `Lifespan::Ticks`
+pub fn tick_fixed_biological_clock<T>(mut queries: Query<&mut BiologicalClock, With<T>>)
+where
+    T: Component,
+{
+    for mut biological_clock in queries.iter_mut() {
+        biological_clock.tick_fixed();
+    }
+}
+
+/// Predicate that decides if an entity has expired.
+pub type ExpiryPredicate = Arc<dyn Fn(EntityRef) -> bool + Send + Sync>;
+
+/// Resource that holds the conditions under which the entities with `T` expire, besides their lifespan.
+///
+/// An entity expires as soon as any of the predicates is true.
+#[derive(Resource)]
+pub struct ExpiryCondition<T> {
+    pub predicates: Vec<ExpiryPredicate>,
+    _marker: PhantomData<T>,
+}
+
+impl<T> Default for ExpiryCondition<T> {
+    fn default() -> Self {
+        Self {
+            predicates: Vec::new(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> ExpiryCondition<T> {
+    /// Check if any of the predicates is true for the entity.
+    pub fn is_met(&self, entity: EntityRef) -> bool {
+        self.predicates.iter().any(|predicate| predicate(entity))
+    }
+}
+
+/// Acts like a garbage collector to remove entities that meet one of their expiry conditions
+pub fn expire_by_condition<T>(mut commands: Commands, condition: Res<ExpiryCondition<T>>, queries: Query<EntityRef, With<T>>)
+where
+    T: Component,
+{
+    for entity in queries.iter() {
+        if condition.is_met(entity) {
+            expire_entity(&mut commands, entity.id());
+        }
+    }
+}
+
+/// Records the expiry of the entities with an identifier in the lineage
+pub fn record_expiry<T>(trigger: Trigger<Expired>, queries: Query<(&Identifier<T>, Option<&Parent>, Option<&Generation>)>, identifiers: Query<&Identifier<T>>, mut lineage: ResMut<Lineage<T>>)
+where
+    T: Clone + std::fmt::Debug + PartialEq + Send + Sync + 'static,
+{
+    let Ok((self_identifier, parent, generation)) = queries.get(trigger.entity()) else {
+        return;
+    };
+    let parent_identifier = parent.and_then(|parent| identifiers.get(parent.get()).ok()).cloned();
+    debug!("Entity {:?} expired.", self_identifier);
+    lineage.add_history(History::new(Action::Expire, parent_identifier, self_identifier.clone(), Ok(())).with_generation(generation.map_or(0, |generation| generation.0)));
+}
+
+/// Acts like a garbage collector to remove entities that have exceeded their own lifetime
+pub fn refresh_by_own_lifetime<T>(commands: Commands, time: Res<Time>, queries: Query<(Entity, &mut BiologicalClock, &T), With<T>>)
+where
+    T: Component + BiologicalTrait,
+{
+    age_by_own_lifetime(commands, time.delta(), queries);
+}
+
+/// Acts like a garbage collector to remove entities that have exceeded their own lifetime, aged with the clock `C`
+pub fn refresh_by_own_lifetime_with_clock<T, C>(commands: Commands, clock: Res<C>, queries: Query<(Entity, &mut BiologicalClock, &T), With<T>>)
+where
+    T: Component + BiologicalTrait,
+    C: LifetimeClock,
+{
+    age_by_own_lifetime(commands, clock.delta(), queries);
+}
+
+/// Acts like a garbage collector to remove entities that have exceeded their parent's lifetime
+pub fn refresh_by_parent_lifetime<T, U>(commands: Commands, time: Res<Time>, parent_queries: Query<&T>, child_queries: Query<(&Parent, Entity, &mut BiologicalClock), With<U>>)
+where
+    T: Component + BiologicalTrait,
+    U: Component,
+{
+    age_by_parent_lifetime(commands, time.delta(), parent_queries, child_queries);
+}
+
+/// Acts like a garbage collector to remove entities that have exceeded their parent's lifetime, aged with the clock `C`
+pub fn refresh_by_parent_lifetime_with_clock<T, U, C>(commands: Commands, clock: Res<C>, parent_queries: Query<&T>, child_queries: Query<(&Parent, Entity, &mut BiologicalClock), With<U>>)
+where
+    T: Component + BiologicalTrait,
+    U: Component,
+    C: LifetimeClock,
+{
+    age_by_parent_lifetime(commands, clock.delta(), parent_queries, child_queries);
+}
+
+fn age_by_own_lifetime<T>(mut commands: Commands, delta: Duration, mut queries: Query<(Entity, &mut BiologicalClock, &T), With<T>>)
+where
+    T: Component + BiologicalTrait,
+{
+    for (entity, mut bioglical_clock, component) in queries.iter_mut() {
+        if is_dead(component, &bioglical_clock) {
+            expire_entity(&mut commands, entity);
+        } else {
+            bioglical_clock.tick(delta);
+        }
+    }
+}
+
+fn age_by_parent_lifetime<T, U>(mut commands: Commands, delta: Duration, parent_queries: Query<&T>, mut child_queries: Query<(&Parent, Entity, &mut BiologicalClock), With<U>>)
+where
+    T: Component + BiologicalTrait,
+    U: Component,
+{
+    for (parent, child_entity, mut child_bioglical_clock) in child_queries.iter_mut() {
+        match parent_queries.get(**parent) {
+            Ok(parent_component) => {
+                if is_dead(parent_component, &child_bioglical_clock) {
+                    expire_entity(&mut commands, child_entity);
+                } else {
+                    child_bioglical_clock.tick(delta);
+                }
+            }
+            Err(_) => {
+                warn!("Parent entity does not exist");
+                continue;
+            }
+        }
+    }
+}
+
+/// Check if the component's life is over, according to the clock.
+fn is_dead<T>(component: &T, clock: &BiologicalClock) -> bool
+where
+    T: BiologicalTrait,
+{
+    match component.get_lifespan() {
+        Lifespan::UntilCondition => component.is_expired(),
+        lifespan => lifespan.is_exceeded_by(clock),
+    }
+}
+
+/// Trigger `Expired` on the entity, then despawn it with all of its children.
+///
+/// An entity that is expired more than once in a frame, by its lifespan and by a condition or along with its parent, only expires the first time.
+pub(crate) fn expire_entity(commands: &mut Commands, entity: Entity) {
+    commands.queue(move |world: &mut World| {
+        if world.get_entity(entity).is_err() {
+            return;
+        }
+        world.trigger_targets(Expired, entity);
+        // Dark, but kills of all the children if the parent dies
+        world.entity_mut(entity).despawn_recursive();
+        debug!("Entity died");
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Component)]
+    struct Mayfly(bool);
+
+    impl BiologicalTrait for Mayfly {
+        fn get_lifetime(&self) -> Duration {
+            Duration::from_secs(10)
+        }
+    }
+
+    #[test]
+    fn manual_clock_applies_every_step_once() {
+        let mut app = App::new();
+        app.add_plugins(LifetimePlugin::<Mayfly, ManualClock>::default());
+        let entity = app.world_mut().spawn(Mayfly(false)).id();
+
+        app.world_mut().resource_mut::<ManualClock>().step(Duration::from_secs(4));
+        for _ in 0..4 {
+            app.update();
+        }
+        assert_eq!(app.world().get::<BiologicalClock>(entity).unwrap().age(), Duration::from_secs(4));
+
+        app.world_mut().resource_mut::<ManualClock>().step(Duration::from_secs(3));
+        app.world_mut().resource_mut::<ManualClock>().step(Duration::from_secs(4));
+        app.update();
+        assert!(app.world().get_entity(entity).is_ok());
+        app.update();
+        assert!(app.world().get_entity(entity).is_err());
+        assert_eq!(app.world().resource::<ManualClock>().elapsed(), Duration::from_secs(11));
+    }
+
+    #[derive(Resource, Default)]
+    struct Expirations(usize);
+
+    #[test]
+    fn expires_once_by_lifespan_and_condition() {
+        let mut app = App::new();
+        app.add_plugins(LifetimePlugin::<Mayfly, ManualClock>::default().with_expiry_condition(|mayfly: &Mayfly| mayfly.0));
+        app.init_resource::<Expirations>();
+        app.add_observer(|_: Trigger<Expired>, mut expirations: ResMut<Expirations>| expirations.0 += 1);
+        let entity = app.world_mut().spawn(Mayfly(false)).id();
+        app.world_mut().resource_mut::<ManualClock>().step(Duration::from_secs(11));
+        app.update();
+
+        app.world_mut().get_mut::<Mayfly>(entity).unwrap().0 = true;
+        app.update();
+        assert!(app.world().get_entity(entity).is_err());
+        assert_eq!(app.world().resource::<Expirations>().0, 1);
+    }
+}
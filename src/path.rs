@@ -0,0 +1,231 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+use std::convert::Infallible;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use crate::Identifier;
+
+/// Address of an entity by the identifiers from its root down to itself, like `Building/Level/Room`.
+///
+/// A path always holds at least the identifier of the root.
+/// When it is formatted, separators and backslashes inside the identifiers are escaped with a backslash, so it parses back to the same path.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "Vec<Identifier<T>>", bound(deserialize = "T: serde::Deserialize<'de>")))]
+pub struct FamilyPath<T> {
+    identifiers: Vec<Identifier<T>>,
+}
+
+impl<T> FamilyPath<T> {
+    /// Separator between the identifiers when the path is formatted or parsed.
+    pub const SEPARATOR: char = '/';
+
+    /// Escape of a separator or an escape inside an identifier when the path is formatted or parsed.
+    pub const ESCAPE: char = '\\';
+
+    /// Create the path of a root.
+    pub fn root(identifier: T) -> Self {
+        Self {
+            identifiers: vec![Identifier(identifier)],
+        }
+    }
+
+    /// Create a path from the identifiers, from the root down, if there is at least one.
+    pub fn from_identifiers(identifiers: Vec<Identifier<T>>) -> Option<Self> {
+        (!identifiers.is_empty()).then_some(Self { identifiers })
+    }
+
+    /// Extend the path with a child.
+    pub fn join(mut self, identifier: T) -> Self {
+        self.identifiers.push(Identifier(identifier));
+        self
+    }
+
+    /// Get the identifiers, from the root down.
+    pub fn identifiers(&self) -> &[Identifier<T>] {
+        &self.identifiers
+    }
+
+    /// Get the identifier of the root.
+    pub fn root_identifier(&self) -> &Identifier<T> {
+        &self.identifiers[0]
+    }
+
+    /// Get the identifier of the entity that the path leads to.
+    pub fn leaf(&self) -> &Identifier<T> {
+        &self.identifiers[self.identifiers.len() - 1]
+    }
+
+    /// Get the identifier of the parent of the leaf, if it is not a root.
+    pub fn parent_identifier(&self) -> Option<&Identifier<T>> {
+        self.identifiers.len().checked_sub(2).map(|index| &self.identifiers[index])
+    }
+
+    /// Get the `FamilyDepth` that the leaf has, 0 for a root.
+    pub fn depth(&self) -> usize {
+        self.identifiers.len() - 1
+    }
+
+    /// Check if the path leads to a root.
+    pub fn is_root(&self) -> bool {
+        self.identifiers.len() == 1
+    }
+}
+
+impl<T> FamilyPath<T>
+where
+    T: Clone,
+{
+    /// Get the path of the parent of the leaf, if it is not a root.
+    pub fn parent(&self) -> Option<Self> {
+        Self::from_identifiers(self.identifiers[..self.identifiers.len() - 1].to_vec())
+    }
+}
+
+impl<T> TryFrom<Vec<Identifier<T>>> for FamilyPath<T> {
+    type Error = FamilyPathError<Infallible>;
+
+    fn try_from(identifiers: Vec<Identifier<T>>) -> Result<Self, Self::Error> {
+        Self::from_identifiers(identifiers).ok_or(FamilyPathError::Empty)
+    }
+}
+
+/// The path is serialized as the list of its identifiers, and deserialized only if the list is not empty.
+#[cfg(feature = "serde")]
+impl<T> serde::Serialize for FamilyPath<T>
+where
+    T: serde::Serialize,
+{
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.identifiers.serialize(serializer)
+    }
+}
+
+impl<T> Display for FamilyPath<T>
+where
+    T: Display,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (index, identifier) in self.identifiers.iter().enumerate() {
+            if index > 0 {
+                write!(f, "{}", Self::SEPARATOR)?;
+            }
+            for character in identifier.0.to_string().chars() {
+                if character == Self::SEPARATOR || character == Self::ESCAPE {
+                    write!(f, "{}", Self::ESCAPE)?;
+                }
+                write!(f, "{}", character)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Error when a `FamilyPath` cannot be parsed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FamilyPathError<E> {
+    /// The path has no identifier.
+    Empty,
+    /// One of the identifiers cannot be parsed.
+    InvalidIdentifier(E),
+}
+
+impl<E> Display for FamilyPathError<E>
+where
+    E: Display,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FamilyPathError::Empty => write!(f, "path has no identifier"),
+            FamilyPathError::InvalidIdentifier(error) => write!(f, "invalid identifier in path: {}", error),
+        }
+    }
+}
+
+impl<E> std::error::Error for FamilyPathError<E> where E: std::fmt::Debug + Display {}
+
+impl<T> FromStr for FamilyPath<T>
+where
+    T: FromStr,
+{
+    type Err = FamilyPathError<T::Err>;
+
+    /// Parse the identifiers between the separators, ignoring a leading or trailing separator.
+    ///
+    /// A character after an escape is part of the identifier, even if it is a separator.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut segments = vec![String::new()];
+        let mut characters = s.chars();
+        while let Some(character) = characters.next() {
+            match character {
+                Self::ESCAPE => segments.last_mut().unwrap().push(characters.next().unwrap_or(Self::ESCAPE)),
+                Self::SEPARATOR => segments.push(String::new()),
+                character => segments.last_mut().unwrap().push(character),
+            }
+        }
+        if segments.len() > 1 && segments[0].is_empty() {
+            segments.remove(0);
+        }
+        if segments.len() > 1 && segments[segments.len() - 1].is_empty() {
+            segments.pop();
+        }
+        if segments.len() == 1 && segments[0].is_empty() {
+            return Err(FamilyPathError::Empty);
+        }
+        let identifiers = segments
+            .iter()
+            .map(|identifier| identifier.parse().map(Identifier).map_err(FamilyPathError::InvalidIdentifier))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { identifiers })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_formats_paths() {
+        let path: FamilyPath<String> = "Building/Level/Room".parse().unwrap();
+        assert_eq!(path, FamilyPath::root("Building".to_string()).join("Level".to_string()).join("Room".to_string()));
+        assert_eq!(path.to_string(), "Building/Level/Room");
+        assert_eq!("/Building/Level/Room/".parse::<FamilyPath<String>>().unwrap(), path);
+        assert_eq!("".parse::<FamilyPath<String>>(), Err(FamilyPathError::Empty));
+        assert_eq!("/".parse::<FamilyPath<String>>(), Err(FamilyPathError::Empty));
+        assert!(matches!("1/x/3".parse::<FamilyPath<u32>>(), Err(FamilyPathError::InvalidIdentifier(_))));
+    }
+
+    #[test]
+    fn escapes_separators_inside_identifiers() {
+        let path = FamilyPath::root("a/b".to_string()).join("c\\".to_string()).join("/".to_string());
+        assert_eq!(path.to_string(), "a\\/b/c\\\\/\\/");
+        assert_eq!(path.to_string().parse::<FamilyPath<String>>().unwrap(), path);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn deserializes_only_paths_with_identifiers() {
+        let path: FamilyPath<String> = "Building/Level/Room".parse().unwrap();
+        let json = serde_json::to_string(&path).unwrap();
+        assert_eq!(json, r#"["Building","Level","Room"]"#);
+        assert_eq!(serde_json::from_str::<FamilyPath<String>>(&json).unwrap(), path);
+        assert!(serde_json::from_str::<FamilyPath<String>>("[]").is_err());
+    }
+}
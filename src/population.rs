@@ -0,0 +1,320 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+use bevy_ecs::prelude::*;
+use bevy_ecs::system::SystemParam;
+use bevy_hierarchy::prelude::*;
+use bevy_log::prelude::*;
+
+use std::cmp::Reverse;
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use crate::{capture_evicted, Action, BiologicalClock, Constraints, FamilyError, FamilyQuery, Generation, History, Identifier, Lineage};
+
+/// A component that marks the entities spawned from the bundle `U`.
+#[derive(Component)]
+pub struct FamilyMember<U> {
+    _marker: PhantomData<U>,
+}
+
+impl<U> Default for FamilyMember<U> {
+    fn default() -> Self {
+        Self { _marker: PhantomData }
+    }
+}
+
+/// What to do when a new entity would exceed a population cap.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CapPolicy {
+    /// Reject the new entity with `FamilyError::PopulationCapReached`.
+    #[default]
+    Reject,
+    /// Despawn the oldest entity by its `BiologicalClock` to make room for the new entity.
+    ///
+    /// As an update respawns the entity with a new clock, this is also the least recently updated entity.
+    /// Entities of the same age, such as the ones without a clock, are evicted by their lowest `Generation`, then in the order they were spawned.
+    EvictOldest,
+}
+
+/// Resource that limits how many entities of the bundle `U` can exist.
+#[derive(Resource)]
+pub struct PopulationCap<U> {
+    pub per_type: Option<usize>,
+    pub per_parent: Option<usize>,
+    pub policy: CapPolicy,
+    _marker: PhantomData<U>,
+}
+
+impl<U> Default for PopulationCap<U> {
+    fn default() -> Self {
+        Self {
+            per_type: None,
+            per_parent: None,
+            policy: CapPolicy::default(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<U> PopulationCap<U> {
+    /// Limit the number of entities of the bundle in the whole world.
+    pub fn with_per_type(mut self, max: usize) -> Self {
+        self.per_type = Some(max);
+        self
+    }
+
+    /// Limit the number of entities of the bundle under each parent.
+    pub fn with_per_parent(mut self, max: usize) -> Self {
+        self.per_parent = Some(max);
+        self
+    }
+
+    /// Select what to do when a cap is reached.
+    pub fn with_policy(mut self, policy: CapPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+}
+
+/// Event that is sent when an entity is evicted to make room under a population cap
+#[derive(Event)]
+pub struct Evicted<V> {
+    pub entity: Entity,
+    pub parent_identifier: Option<Identifier<V>>,
+    pub self_identifier: Identifier<V>,
+}
+
+/// Changes to the population that are not applied to the world yet.
+#[derive(Default)]
+pub struct Census {
+    spawned: Vec<Option<Entity>>,
+    evicted: Vec<Entity>,
+}
+
+/// Data of a member that is needed to find the oldest member under a parent.
+type MemberData = (Entity, Option<&'static Parent>, Option<&'static BiologicalClock>);
+
+/// System parameter that enforces the `PopulationCap` of the bundle `U` and the `FamilyConstraints` of the identifier `V` when entities are created
+#[derive(SystemParam)]
+pub struct Population<'w, 's, U, V>
+where
+    U: Bundle,
+    V: Send + Sync + 'static,
+{
+    members: Query<'w, 's, MemberData, With<FamilyMember<U>>>,
+    generations: Query<'w, 's, &'static Generation>,
+    cap: Option<Res<'w, PopulationCap<U>>>,
+    evicted_events: EventWriter<'w, Evicted<V>>,
+    census: Local<'s, Census>,
+    constraints: Constraints<'w, 's, V>,
+}
+
+impl<U, V> Population<'_, '_, U, V>
+where
+    U: Bundle,
+    V: Clone + std::fmt::Debug + PartialEq + Send + Sync + 'static,
+{
+    /// Start counting the changes of a new run of the system.
+    pub fn begin(&mut self) {
+        self.census.spawned.clear();
+        self.census.evicted.clear();
+        self.constraints.begin();
+    }
+
+    /// Make room for a new entity under the parent, evicting entities if the policy allows it.
+    ///
+    /// The family constraints are checked first, as they never evict. On success, the new entity is counted as spawned.
+    pub fn make_room(&mut self, parent: Option<Entity>, family: &FamilyQuery<V>, commands: &mut Commands, lineage: &mut Lineage<V>) -> Result<(), FamilyError> {
+        self.constraints.check(parent)?;
+        let Some(cap) = &self.cap else {
+            self.census.spawned.push(parent);
+            self.constraints.count(parent);
+            return Ok(());
+        };
+        let (per_type, per_parent, policy) = (cap.per_type, cap.per_parent, cap.policy);
+
+        let mut evictions = Vec::new();
+        if let (Some(max), Some(parent)) = (per_parent, parent) {
+            let spawned = self.census.spawned.iter().filter(|spawned| **spawned == Some(parent)).count();
+            if self.count(Some(parent)) + spawned >= max {
+                evictions.push(self.find_oldest(Some(parent), policy, &evictions)?);
+            }
+        }
+        if let Some(max) = per_type {
+            let living = self.count(None) - evictions.len();
+            if living + self.census.spawned.len() >= max {
+                evictions.push(self.find_oldest(None, policy, &evictions)?);
+            }
+        }
+
+        for entity in evictions {
+            self.evict(entity, family, commands, lineage);
+        }
+        self.census.spawned.push(parent);
+        self.constraints.count(parent);
+        Ok(())
+    }
+
+    /// Make room for the missing ancestors of a new entity and the entity itself, as a chain under the parent, without counting them.
+    ///
+    /// `members` tells which of the ancestors are entities of the bundle `U`, the new entity always is.
+    /// Every check is done before any entity is evicted, so nothing changes when the chain cannot be created.
+    pub fn make_room_for_ancestors(&mut self, parent: Option<Entity>, members: &[bool], family: &FamilyQuery<V>, commands: &mut Commands, lineage: &mut Lineage<V>) -> Result<(), FamilyError> {
+        self.constraints.check_chain(parent, members.len() + 1)?;
+        let Some(cap) = &self.cap else {
+            return Ok(());
+        };
+        let (per_type, per_parent, policy) = (cap.per_type, cap.per_parent, cap.policy);
+
+        let mut evictions = Vec::new();
+        if let Some(max) = per_parent {
+            // the entities after the first one are the only child of a new parent
+            let first_is_member = members.first().copied().unwrap_or(true);
+            if let (Some(parent), true) = (parent, first_is_member) {
+                let spawned = self.census.spawned.iter().filter(|spawned| **spawned == Some(parent)).count();
+                if self.count(Some(parent)) + spawned >= max {
+                    evictions.push(self.find_oldest(Some(parent), policy, &evictions)?);
+                }
+            }
+            if max == 0 && members.iter().skip(1).chain(std::iter::once(&true)).any(|member| *member) {
+                return Err(FamilyError::PopulationCapReached);
+            }
+        }
+        if let Some(max) = per_type {
+            let new_members = members.iter().filter(|member| **member).count() + 1;
+            while self.count(None) - evictions.len() + self.census.spawned.len() + new_members > max {
+                evictions.push(self.find_oldest(None, policy, &evictions)?);
+            }
+        }
+
+        for entity in evictions {
+            self.evict(entity, family, commands, lineage);
+        }
+        Ok(())
+    }
+
+    /// Count a missing ancestor that is spawned under the parent, in the population if it is an entity of the bundle `U`.
+    pub fn count_ancestor(&mut self, parent: Option<Entity>, entity: Entity, member: bool) {
+        if member {
+            self.census.spawned.push(parent);
+        }
+        self.constraints.count_ancestor(parent, entity);
+    }
+
+    /// Iterate over the living members, under the parent if there is one.
+    fn living(&self, parent: Option<Entity>) -> impl Iterator<Item = (Entity, Option<&Parent>, Option<&BiologicalClock>)> {
+        self.members
+            .iter()
+            .filter(move |(entity, member_parent, _)| !self.census.evicted.contains(entity) && (parent.is_none() || member_parent.map(|member_parent| member_parent.get()) == parent))
+    }
+
+    /// Count the living members, under the parent if there is one.
+    fn count(&self, parent: Option<Entity>) -> usize {
+        self.living(parent).count()
+    }
+
+    /// Find the oldest living member to evict, under the parent if there is one.
+    ///
+    /// Ties of age go to the lowest generation, then to the lowest entity, which was spawned first unless its index was reused.
+    fn find_oldest(&self, parent: Option<Entity>, policy: CapPolicy, excluded: &[Entity]) -> Result<Entity, FamilyError> {
+        if policy == CapPolicy::Reject {
+            return Err(FamilyError::PopulationCapReached);
+        }
+        self.living(parent)
+            .filter(|(entity, _, _)| !excluded.contains(entity))
+            .min_by_key(|(entity, _, biological_clock)| {
+                let age = biological_clock.map_or(Duration::ZERO, |biological_clock| biological_clock.age());
+                (Reverse(age), self.generations.get(*entity).map_or(0, |generation| generation.0), *entity)
+            })
+            .map(|(entity, _, _)| entity)
+            .ok_or(FamilyError::PopulationCapReached)
+    }
+
+    /// Despawn the member and record the eviction.
+    fn evict(&mut self, entity: Entity, family: &FamilyQuery<V>, commands: &mut Commands, lineage: &mut Lineage<V>) {
+        self.census.evicted.push(entity);
+        commands.queue(move |world: &mut World| capture_evicted::<V>(world, entity));
+        commands.entity(entity).despawn_recursive();
+        let generation = self.generations.get(entity).map_or(0, |generation| generation.0);
+
+        let Some(self_identifier) = family.identifier(entity) else {
+            return;
+        };
+        let parent_identifier = family.parent_of(entity).map(|(_, identifier)| identifier.clone());
+        debug!("Entity {:?} is evicted to make room under the population cap.", self_identifier);
+        lineage.add_history(History::new(Action::Evict, parent_identifier.clone(), self_identifier.clone(), Ok(())).with_generation(generation));
+        self.evicted_events.send(Evicted {
+            entity,
+            parent_identifier,
+            self_identifier: self_identifier.clone(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{app, identifiers, send, Node};
+    use crate::CudEvent;
+    use bevy_app::prelude::*;
+
+    /// Get the result of the last history.
+    fn last_result(app: &App) -> Result<(), FamilyError> {
+        app.world().resource::<Lineage<String>>().histories.last().unwrap().result
+    }
+
+    #[test]
+    fn rejects_the_entity_over_the_cap() {
+        let mut app = app();
+        app.insert_resource(PopulationCap::<Node>::default().with_per_type(2));
+        send(&mut app, CudEvent::create_parent("A".to_string(), Node::default()));
+        send(&mut app, CudEvent::create_parent("B".to_string(), Node::default()));
+        send(&mut app, CudEvent::create_parent("C".to_string(), Node::default()));
+        assert_eq!(last_result(&app), Err(FamilyError::PopulationCapReached));
+        assert_eq!(identifiers(&mut app), ["A", "B"]);
+    }
+
+    #[test]
+    fn evicts_the_oldest_entity_under_the_parent() {
+        let mut app = app();
+        send(&mut app, CudEvent::create_parent("P".to_string(), Node::default()));
+        send(&mut app, CudEvent::create_parent("Q".to_string(), Node::default()));
+        app.insert_resource(PopulationCap::<Node>::default().with_per_parent(2).with_policy(CapPolicy::EvictOldest));
+        send(&mut app, CudEvent::create_child("P".to_string(), "A".to_string(), Node::default()));
+        send(&mut app, CudEvent::create_child("Q".to_string(), "B".to_string(), Node::default()));
+        send(&mut app, CudEvent::create_child("P".to_string(), "C".to_string(), Node::default()));
+        send(&mut app, CudEvent::create_child("P".to_string(), "D".to_string(), Node::default()));
+        assert_eq!(last_result(&app), Ok(()));
+        assert_eq!(identifiers(&mut app), ["B", "C", "D", "P", "Q"]);
+    }
+
+    #[test]
+    fn evicts_the_oldest_entity_of_the_type() {
+        let mut app = app();
+        app.insert_resource(PopulationCap::<Node>::default().with_per_type(2).with_policy(CapPolicy::EvictOldest));
+        send(&mut app, CudEvent::create_parent("A".to_string(), Node::default()));
+        send(&mut app, CudEvent::create_parent("B".to_string(), Node::default()));
+        // without clocks, the entities are evicted in the order they were spawned
+        send(&mut app, CudEvent::create_parent("C".to_string(), Node::default()));
+        assert_eq!(identifiers(&mut app), ["B", "C"]);
+        send(&mut app, CudEvent::create_parent("A".to_string(), Node::default()));
+        assert_eq!(identifiers(&mut app), ["A", "C"]);
+        assert_eq!(app.world_mut().query::<&Generation>().iter(app.world()).map(|generation| generation.0).max(), Some(2));
+    }
+}
@@ -0,0 +1,165 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+use bevy_ecs::prelude::*;
+use bevy_ecs::system::SystemParam;
+use bevy_hierarchy::prelude::*;
+
+use crate::{FamilyPath, Identifier};
+
+/// System parameter that looks up the entities of the family trees by their identifier `T`
+///
+/// Every lookup returns the entities together with their identifiers. When an identifier is used more than once, the first entity found is used.
+#[derive(SystemParam)]
+pub struct FamilyQuery<'w, 's, T>
+where
+    T: Send + Sync + 'static,
+{
+    identifiers: Query<'w, 's, (Entity, &'static Identifier<T>)>,
+    parents: Query<'w, 's, &'static Parent>,
+    children: Query<'w, 's, &'static Children>,
+}
+
+impl<T> FamilyQuery<'_, '_, T>
+where
+    T: PartialEq + Send + Sync + 'static,
+{
+    /// Get the entity by the identifier.
+    pub fn get(&self, identifier: &Identifier<T>) -> Option<Entity> {
+        self.identifiers.iter().find(|(_, id)| *id == identifier).map(|(entity, _)| entity)
+    }
+
+    /// Get the entity by its path, starting from a root.
+    pub fn get_by_path(&self, path: &FamilyPath<T>) -> Option<Entity> {
+        let mut identifiers = path.identifiers().iter();
+        let root_identifier = identifiers.next()?;
+        let root = self.identifiers.iter().find(|(entity, id)| *id == root_identifier && self.parent_of(*entity).is_none()).map(|(entity, _)| entity)?;
+        identifiers.try_fold(root, |parent, identifier| self.children_of(parent).find(|(_, id)| *id == identifier).map(|(entity, _)| entity))
+    }
+
+    /// Get the identifier of the entity.
+    pub fn identifier(&self, entity: Entity) -> Option<&Identifier<T>> {
+        self.identifiers.get(entity).ok().map(|(_, identifier)| identifier)
+    }
+
+    /// Get the child by its identifier, only if it is under the parent.
+    pub fn child_of(&self, parent_identifier: &Identifier<T>, child_identifier: &Identifier<T>) -> Option<Entity> {
+        self.children(parent_identifier).find(|(_, id)| *id == child_identifier).map(|(entity, _)| entity)
+    }
+
+    /// Check that the entity exists, under the parent if there is one.
+    pub fn exists(&self, parent_identifier: Option<&Identifier<T>>, identifier: &Identifier<T>) -> bool {
+        match parent_identifier {
+            Some(parent_identifier) => self.child_of(parent_identifier, identifier).is_some(),
+            None => self.get(identifier).is_some(),
+        }
+    }
+
+    /// Get the parent of the entity, if it has an identifier.
+    pub fn parent(&self, identifier: &Identifier<T>) -> Option<(Entity, &Identifier<T>)> {
+        self.parent_of(self.get(identifier)?)
+    }
+
+    /// Iterate over the children of the entity that have an identifier.
+    pub fn children(&self, identifier: &Identifier<T>) -> impl Iterator<Item = (Entity, &Identifier<T>)> + '_ {
+        self.get(identifier).into_iter().flat_map(|entity| self.children_of(entity))
+    }
+
+    /// Iterate over the ancestors of the entity that have an identifier, from its parent up to its root.
+    pub fn ancestors(&self, identifier: &Identifier<T>) -> impl Iterator<Item = (Entity, &Identifier<T>)> + '_ {
+        self.get(identifier)
+            .into_iter()
+            .flat_map(|entity| self.parents.iter_ancestors(entity))
+            .filter_map(|ancestor| self.identifiers.get(ancestor).ok())
+    }
+
+    /// Iterate over the descendants of the entity that have an identifier, breadth first.
+    pub fn descendants(&self, identifier: &Identifier<T>) -> impl Iterator<Item = (Entity, &Identifier<T>)> + '_ {
+        self.get(identifier)
+            .into_iter()
+            .flat_map(|entity| self.children.iter_descendants(entity))
+            .filter_map(|descendant| self.identifiers.get(descendant).ok())
+    }
+
+    /// Iterate over the other children of the parent of the entity that have an identifier.
+    pub fn siblings(&self, identifier: &Identifier<T>) -> impl Iterator<Item = (Entity, &Identifier<T>)> + '_ {
+        let entity = self.get(identifier);
+        entity
+            .and_then(|entity| self.parents.get(entity).ok())
+            .into_iter()
+            .flat_map(|parent| self.children_of(parent.get()))
+            .filter(move |(sibling, _)| Some(*sibling) != entity)
+    }
+
+    /// Get the path of the entity, from its root down.
+    pub fn path_of(&self, entity: Entity) -> Option<FamilyPath<T>>
+    where
+        T: Clone,
+    {
+        let mut identifiers = vec![self.identifier(entity)?.clone()];
+        identifiers.extend(self.parents.iter_ancestors(entity).map_while(|ancestor| self.identifier(ancestor).cloned()));
+        identifiers.reverse();
+        FamilyPath::from_identifiers(identifiers)
+    }
+
+    /// Get the parent of the entity, if it has an identifier.
+    pub fn parent_of(&self, entity: Entity) -> Option<(Entity, &Identifier<T>)> {
+        self.parents.get(entity).ok().and_then(|parent| self.identifiers.get(parent.get()).ok())
+    }
+
+    /// Iterate over the children of the entity that have an identifier.
+    pub fn children_of(&self, entity: Entity) -> impl Iterator<Item = (Entity, &Identifier<T>)> + '_ {
+        self.children
+            .get(entity)
+            .into_iter()
+            .flatten()
+            .filter_map(|child| self.identifiers.get(*child).ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{app, send, Node};
+    use crate::CudEvent;
+    use bevy_ecs::system::SystemState;
+
+    #[test]
+    fn looks_up_identifiers_scoped_by_their_parent() {
+        let mut app = app();
+        for path in ["A/Level/Room", "B/Level/Room", "A/Level/Kitchen"] {
+            send(&mut app, CudEvent::create_with_ancestors(path.parse().unwrap(), Node::default()));
+        }
+        let mut state = SystemState::<FamilyQuery<String>>::new(app.world_mut());
+        let family = state.get(app.world());
+        let get = |path: &str| family.get_by_path(&path.parse().unwrap());
+        let identifiers = |entities: Vec<(Entity, &Identifier<String>)>| entities.into_iter().map(|(_, identifier)| identifier.0.clone()).collect::<Vec<_>>();
+
+        let (a_room, b_room) = (get("A/Level/Room").unwrap(), get("B/Level/Room").unwrap());
+        assert_ne!(a_room, b_room);
+        assert_eq!(family.path_of(b_room).unwrap().to_string(), "B/Level/Room");
+        assert_eq!(get("Level"), None);
+        assert_eq!(get("B/Level/Kitchen"), None);
+
+        let kitchen = Identifier("Kitchen".to_string());
+        assert_eq!(identifiers(family.siblings(&kitchen).collect()), ["Room"]);
+        assert_eq!(family.siblings(&kitchen).next().map(|(entity, _)| entity), Some(a_room));
+        assert_eq!(identifiers(family.ancestors(&kitchen).collect()), ["Level", "A"]);
+        assert_eq!(family.ancestors(&kitchen).next().map(|(entity, _)| entity), get("A/Level"));
+    }
+}
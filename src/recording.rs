@@ -0,0 +1,130 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+use bevy_ecs::prelude::*;
+use bevy_ecs::system::SystemParam;
+use bevy_log::prelude::*;
+
+use crate::{capture_after, capture_before, Action, CudEvent, FamilyPath, FamilyUndo, Identifier};
+
+/// Resource that turns the bundles `U` of the successful `CudEvent`s into payloads for the `EventLog`.
+#[derive(Resource)]
+pub struct BundleEncoder<U> {
+    /// Name of the bundle in the `EventLog`, to find its decoder when the log is replayed.
+    pub name: String,
+    pub encode: fn(&U) -> Result<String, String>,
+}
+
+/// A successful `CudEvent` with its bundle encoded as a payload.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RecordedEvent<V> {
+    /// Frame of the `EventLog` that the event was handled in.
+    pub frame: u64,
+    pub bundle: String,
+    pub action: Action,
+    pub parent_identifier: Option<Identifier<V>>,
+    pub path: Option<FamilyPath<V>>,
+    pub self_identifier: Identifier<V>,
+    pub payload: String,
+}
+
+/// Resource with the successful `CudEvent`s of every bundle with the identifier `V`, in the order that they were handled
+#[derive(Resource, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EventLog<V> {
+    pub events: Vec<RecordedEvent<V>>,
+    /// Number of frames since the log started.
+    pub frame: u64,
+}
+
+impl<V> Default for EventLog<V> {
+    fn default() -> Self {
+        Self { events: Vec::new(), frame: 0 }
+    }
+}
+
+/// Moves the event log to the next frame
+pub fn advance_event_log<V>(mut event_log: ResMut<EventLog<V>>)
+where
+    V: Send + Sync + 'static,
+{
+    event_log.frame += 1;
+}
+
+/// System parameter that records the successful `CudEvent`s of the bundle `U` in the `EventLog`, if there is an encoder for the bundle,
+/// and in the `FamilyUndo`, if there is one
+#[derive(SystemParam)]
+pub struct Recorder<'w, U, V>
+where
+    U: Bundle,
+    V: Send + Sync + 'static,
+{
+    encoder: Option<Res<'w, BundleEncoder<U>>>,
+    event_log: Option<ResMut<'w, EventLog<V>>>,
+    undo: Option<Res<'w, FamilyUndo<V>>>,
+}
+
+impl<U, V> Recorder<'_, U, V>
+where
+    U: Bundle + Clone,
+    V: Clone + std::fmt::Debug + PartialEq + Send + Sync + 'static,
+{
+    /// Keep the state of the entity of the event before it is handled, for the undo history.
+    pub fn begin(&self, event: &CudEvent<U, V>, commands: &mut Commands) {
+        if self.undo.is_none() || *event.get_action() == Action::Touch {
+            return;
+        }
+        let (path, self_identifier) = (event.get_path().clone(), event.get_self_identifier().clone());
+        commands.queue(move |world: &mut World| capture_before::<U, V>(world, path, self_identifier));
+    }
+
+    /// Record the successful event in the event log and the undo history.
+    pub fn record(&mut self, event: &CudEvent<U, V>, commands: &mut Commands) {
+        if self.undo.is_some() && *event.get_action() != Action::Touch {
+            let (action, path, parent_identifier, self_identifier) = (
+                event.get_action().clone(),
+                event.get_path().clone(),
+                event.get_parent_identifier().clone(),
+                event.get_self_identifier().clone(),
+            );
+            commands.queue(move |world: &mut World| capture_after::<V>(world, action, path, parent_identifier, self_identifier));
+        }
+
+        let (Some(encoder), Some(event_log)) = (&self.encoder, &mut self.event_log) else {
+            return;
+        };
+        let payload = match (encoder.encode)(event.get_bundle_ref()) {
+            Ok(payload) => payload,
+            Err(error) => {
+                warn!("Bundle of entity {:?} cannot be recorded: {}.", event.get_self_identifier(), error);
+                return;
+            }
+        };
+        let frame = event_log.frame;
+        event_log.events.push(RecordedEvent {
+            frame,
+            bundle: encoder.name.clone(),
+            action: event.get_action().clone(),
+            parent_identifier: event.get_parent_identifier().clone(),
+            path: event.get_path().clone(),
+            self_identifier: event.get_self_identifier().clone(),
+            payload,
+        });
+    }
+}
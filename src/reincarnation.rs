@@ -0,0 +1,198 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
+use bevy_hierarchy::prelude::*;
+use bevy_log::prelude::*;
+use bevy_time::prelude::*;
+
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{CudEvent, Expired, Generation, Generations, Identifier, LifetimeClock};
+
+/// Function that creates the bundle of a reborn entity, given its identifier and the generation that it is reborn as.
+pub type BundleFactory<U, V> = Arc<dyn Fn(&Identifier<V>, u32) -> U + Send + Sync>;
+
+/// An entity that is waiting to be reborn.
+#[derive(Clone, Debug)]
+pub struct PendingReincarnation<V> {
+    pub parent_identifier: Option<Identifier<V>>,
+    pub self_identifier: Identifier<V>,
+    /// Generation of the entity that expired.
+    pub generation: u32,
+    pub remaining: Duration,
+}
+
+/// Resource that keeps the expired entities of bundle `U` until they are reborn.
+#[derive(Resource)]
+pub struct Reincarnations<U, V> {
+    delay: Duration,
+    factory: BundleFactory<U, V>,
+    pending: Vec<PendingReincarnation<V>>,
+}
+
+impl<U, V> Reincarnations<U, V> {
+    /// Get the entities that are waiting to be reborn.
+    pub fn get_pending(&self) -> &[PendingReincarnation<V>] {
+        &self.pending
+    }
+}
+
+/// Plugin that brings the expired entities with `T` back to life, under the same parent and with the same identifier.
+///
+/// The expiry is recorded in the lineage by the `FamilyPlugin`, the rebirth is recorded by `cud_bundle` with its result.
+///
+/// The reborn entity is created or replaced like with a `CudEvent::create_or_modify_*` event of bundle `U`, after a delay on the clock `C`.
+pub struct ReincarnationPlugin<T, U, V, C = Time> {
+    delay: Duration,
+    factory: BundleFactory<U, V>,
+    _marker: PhantomData<(T, C)>,
+}
+
+impl<T, U, V, C> ReincarnationPlugin<T, U, V, C>
+where
+    U: Bundle + Clone,
+{
+    /// Create the plugin that rebirths the entities with a copy of the bundle.
+    pub fn new(delay: Duration, bundle: U) -> Self {
+        Self::with_factory(delay, move |_, _| bundle.clone())
+    }
+
+    /// Create the plugin that rebirths the entities with a bundle from the factory.
+    pub fn with_factory(delay: Duration, factory: impl Fn(&Identifier<V>, u32) -> U + Send + Sync + 'static) -> Self {
+        Self {
+            delay,
+            factory: Arc::new(factory),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, U, V, C> Plugin for ReincarnationPlugin<T, U, V, C>
+where
+    T: Component,
+    U: Bundle + Clone,
+    V: Clone + std::fmt::Debug + PartialEq + Send + Sync + 'static,
+    C: LifetimeClock,
+{
+    /// Build the plugin
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Reincarnations::<U, V> {
+            delay: self.delay,
+            factory: self.factory.clone(),
+            pending: Vec::new(),
+        });
+        app.add_observer(queue_reincarnation::<T, U, V>);
+        C::build(app);
+        app.add_systems(Update, reincarnate::<U, V, C>);
+    }
+}
+
+/// Queues the expired entity to be reborn
+pub fn queue_reincarnation<T, U, V>(trigger: Trigger<Expired>, queries: Query<(&Identifier<V>, Option<&Parent>), With<T>>, identifiers: Query<&Identifier<V>>, generations: Query<&Generation>, mut reincarnations: ResMut<Reincarnations<U, V>>)
+where
+    T: Component,
+    U: Bundle,
+    V: Clone + std::fmt::Debug + PartialEq + Send + Sync + 'static,
+{
+    let Ok((self_identifier, parent)) = queries.get(trigger.entity()) else {
+        return;
+    };
+    let parent_identifier = parent.and_then(|parent| identifiers.get(parent.get()).ok()).cloned();
+    let generation = generations.get(trigger.entity()).map_or(0, |generation| generation.0);
+    debug!("Entity {:?} of generation {} expired and will be reborn.", self_identifier, generation);
+    let remaining = reincarnations.delay;
+    reincarnations.pending.push(PendingReincarnation {
+        parent_identifier,
+        self_identifier: self_identifier.clone(),
+        generation,
+        remaining,
+    });
+}
+
+/// Sends the events that bring the expired entities back to life, once their delay has passed on the clock `C`
+pub fn reincarnate<U, V, C>(clock: Res<C>, generations: Res<Generations<V>>, mut reincarnations: ResMut<Reincarnations<U, V>>, mut events: EventWriter<CudEvent<U, V>>)
+where
+    U: Bundle + Clone,
+    V: Clone + std::fmt::Debug + PartialEq + Send + Sync + 'static,
+    C: LifetimeClock,
+{
+    let delta = clock.delta();
+    let reincarnations = reincarnations.as_mut();
+    let factory = reincarnations.factory.clone();
+    reincarnations.pending.retain_mut(|pending| {
+        if pending.remaining > delta {
+            pending.remaining -= delta;
+            return true;
+        }
+
+        // the generation is taken when the entity is reborn, so that the recreations since its expiry are counted
+        let generation = generations.get(&pending.parent_identifier, &pending.self_identifier) + 1;
+        let bundle = factory(&pending.self_identifier, generation);
+        debug!("Entity {:?} is reborn as generation {}.", pending.self_identifier, generation);
+        events.send(CudEvent::reincarnate(pending.parent_identifier.clone(), pending.self_identifier.clone(), generation, bundle));
+        false
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{entity, send, Node};
+    use crate::{Action, FamilyError, Lineage, ManualClock};
+
+    fn app() -> App {
+        let mut app = crate::testing::app();
+        app.add_plugins(ReincarnationPlugin::<Node, Node, String, ManualClock>::new(Duration::ZERO, Node::default()));
+        app
+    }
+
+    fn expire(app: &mut App, identifier: &str) {
+        let entity = entity(app, identifier);
+        app.world_mut().trigger_targets(Expired, entity);
+        app.world_mut().entity_mut(entity).despawn_recursive();
+    }
+
+    fn last_history_of_child(app: &App) -> (Action, Result<(), FamilyError>, u32) {
+        let histories = &app.world().resource::<Lineage<String>>().histories;
+        let history = histories.iter().rev().find(|history| history.child_identifier.is_some()).unwrap();
+        (history.action.clone(), history.result, history.generation)
+    }
+
+    #[test]
+    fn records_the_rebirth_with_its_result() {
+        let mut app = app();
+        send(&mut app, CudEvent::create_parent("P".to_string(), Node::default()));
+        send(&mut app, CudEvent::create_child("P".to_string(), "A".to_string(), Node::default()));
+
+        // the rebirth is sent in one update and handled in the next
+        expire(&mut app, "A");
+        app.update();
+        app.update();
+        assert_eq!(last_history_of_child(&app), (Action::Reincarnate(2), Ok(()), 2));
+
+        expire(&mut app, "A");
+        expire(&mut app, "P");
+        app.update();
+        app.update();
+        assert_eq!(last_history_of_child(&app), (Action::Reincarnate(3), Err(FamilyError::ParentNotFound), 2));
+    }
+}
@@ -0,0 +1,237 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use std::fmt::{Display, Formatter};
+use std::marker::PhantomData;
+
+use crate::{advance_event_log, BundleEncoder, CudEvent, EventLog, RecordedEvent};
+
+/// Function that decodes the payload of a recorded event and sends it as a `CudEvent` in the world.
+pub type BundleDecoder<V> = fn(&mut World, &RecordedEvent<V>) -> Result<(), ReplayError>;
+
+/// Resource with the decoders of the bundles with the identifier `V`, by their name in the `EventLog`.
+#[derive(Resource)]
+pub struct BundleDecoders<V> {
+    decoders: Vec<(String, BundleDecoder<V>)>,
+}
+
+impl<V> Default for BundleDecoders<V> {
+    fn default() -> Self {
+        Self { decoders: Vec::new() }
+    }
+}
+
+impl<V> BundleDecoders<V> {
+    /// Get the decoder of the bundle by its name.
+    pub fn get(&self, name: &str) -> Option<BundleDecoder<V>> {
+        self.decoders.iter().find(|(decoder_name, _)| decoder_name == name).map(|(_, decoder)| *decoder)
+    }
+}
+
+/// Reason that an event log cannot be replayed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReplayError {
+    /// There is no `EventSourcingPlugin` for the bundle with the name.
+    UnknownBundle(String),
+    /// The payload cannot be decoded into the bundle.
+    InvalidPayload(String),
+}
+
+impl Display for ReplayError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplayError::UnknownBundle(name) => write!(f, "no decoder for bundle {}", name),
+            ReplayError::InvalidPayload(error) => write!(f, "invalid payload: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+/// Plugin that records the successful `CudEvent`s of the bundle `U` in the `EventLog`, with the bundle serialized as JSON
+///
+/// The same plugin must be added to the app that replays the log, to decode the bundles.
+pub struct EventSourcingPlugin<U, V> {
+    name: String,
+    _marker: PhantomData<(U, V)>,
+}
+
+impl<U, V> Default for EventSourcingPlugin<U, V> {
+    fn default() -> Self {
+        Self {
+            name: std::any::type_name::<U>().to_string(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<U, V> EventSourcingPlugin<U, V> {
+    /// Record the bundle under the name instead of its type name, so logs survive renaming the type.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+}
+
+impl<U, V> Plugin for EventSourcingPlugin<U, V>
+where
+    U: Bundle + Clone + Serialize + DeserializeOwned,
+    V: Clone + std::fmt::Debug + Send + Sync + 'static,
+{
+    /// Build the plugin
+    fn build(&self, app: &mut App) {
+        app.insert_resource(BundleEncoder::<U> {
+            name: self.name.clone(),
+            encode: encode_bundle::<U>,
+        });
+        if !app.world().contains_resource::<BundleDecoders<V>>() {
+            app.init_resource::<BundleDecoders<V>>();
+            app.init_resource::<EventLog<V>>();
+            app.add_systems(First, advance_event_log::<V>);
+        }
+        app.world_mut().resource_mut::<BundleDecoders<V>>().decoders.push((self.name.clone(), send_recorded_event::<U, V>));
+    }
+}
+
+/// Encode the bundle as JSON
+fn encode_bundle<U>(bundle: &U) -> Result<String, String>
+where
+    U: Serialize,
+{
+    serde_json::to_string(bundle).map_err(|error| error.to_string())
+}
+
+/// Decode the bundle of the recorded event and send it as a `CudEvent`
+fn send_recorded_event<U, V>(world: &mut World, recorded: &RecordedEvent<V>) -> Result<(), ReplayError>
+where
+    U: Bundle + Clone + DeserializeOwned,
+    V: Clone + Send + Sync + 'static,
+{
+    let bundle: U = serde_json::from_str(&recorded.payload).map_err(|error| ReplayError::InvalidPayload(error.to_string()))?;
+    world.send_event(CudEvent::from_recorded(recorded, bundle));
+    Ok(())
+}
+
+/// Replay the event log in the app, one update for every frame that the events were recorded in.
+///
+/// The app needs the same `cud_bundle` systems and `EventSourcingPlugin`s as the app that recorded the log.
+pub fn replay_event_log<V>(app: &mut App, event_log: &EventLog<V>) -> Result<(), ReplayError>
+where
+    V: Send + Sync + 'static,
+{
+    let mut events = event_log.events.iter().peekable();
+    while let Some(frame) = events.peek().map(|recorded| recorded.frame) {
+        while let Some(recorded) = events.next_if(|recorded| recorded.frame == frame) {
+            let decoder = app
+                .world()
+                .get_resource::<BundleDecoders<V>>()
+                .and_then(|decoders| decoders.get(&recorded.bundle))
+                .ok_or_else(|| ReplayError::UnknownBundle(recorded.bundle.clone()))?;
+            decoder(app.world_mut(), recorded)?;
+        }
+        app.update();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cud_bundle, FamilyPlugin, Identifier, Lineage};
+    use serde::Deserialize;
+
+    #[derive(Component, Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Building(String);
+
+    #[derive(Component, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+    struct Room(u32);
+
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_plugins(FamilyPlugin::<String>::default());
+        app.add_plugins(EventSourcingPlugin::<Building, String>::default());
+        app.add_plugins(EventSourcingPlugin::<Room, String>::default().with_name("room"));
+        app.add_event::<CudEvent<Building, String>>();
+        app.add_event::<CudEvent<Room, String>>();
+        app.add_systems(Update, (cud_bundle::<Building, String>, cud_bundle::<Room, String>));
+        app
+    }
+
+    fn send<U>(app: &mut App, event: CudEvent<U, String>)
+    where
+        U: Bundle + Clone,
+    {
+        app.world_mut().send_event(event);
+        app.update();
+    }
+
+    /// Get the identifiers of the rooms with their values, sorted.
+    fn rooms(app: &mut App) -> Vec<(String, u32)> {
+        let mut rooms: Vec<(String, u32)> = app
+            .world_mut()
+            .query::<(&Identifier<String>, &Room)>()
+            .iter(app.world())
+            .map(|(identifier, room)| (identifier.0.clone(), room.0))
+            .collect();
+        rooms.sort();
+        rooms
+    }
+
+    /// Record the events of both bundles and get the log.
+    fn record(app: &mut App) -> EventLog<String> {
+        send(app, CudEvent::create_parent("A".to_string(), Building("a".to_string())));
+        send(app, CudEvent::create_child("A".to_string(), "1".to_string(), Room(1)));
+        send(app, CudEvent::update_child("A".to_string(), "1".to_string(), Room(2)));
+        send(app, CudEvent::create_child("A".to_string(), "2".to_string(), Room(3)));
+        send(app, CudEvent::delete_child("A".to_string(), "2".to_string(), Room::default()));
+        send(app, CudEvent::create_with_ancestors("B/F/3".parse().unwrap(), Room(4)));
+        app.world().resource::<EventLog<String>>().clone()
+    }
+
+    #[test]
+    fn replays_the_recorded_events_into_the_same_trees() {
+        let mut recording = app();
+        let event_log = record(&mut recording);
+        assert_eq!(event_log.events.len(), 6);
+        assert_eq!(event_log.events[0].bundle, std::any::type_name::<Building>());
+        assert_eq!(event_log.events[1].bundle, "room");
+
+        let mut replaying = app();
+        replay_event_log(&mut replaying, &event_log).unwrap();
+        assert_eq!(rooms(&mut replaying), [("1".to_string(), 2), ("3".to_string(), 4)]);
+        assert_eq!(rooms(&mut replaying), rooms(&mut recording));
+        assert_eq!(replaying.world().resource::<Lineage<String>>().snapshot(), recording.world().resource::<Lineage<String>>().snapshot());
+    }
+
+    #[test]
+    fn fails_on_an_unknown_bundle_or_an_invalid_payload() {
+        let mut event_log = record(&mut app());
+
+        let mut unknown = event_log.clone();
+        unknown.events[1].bundle = "unknown".to_string();
+        assert_eq!(replay_event_log(&mut app(), &unknown), Err(ReplayError::UnknownBundle("unknown".to_string())));
+
+        event_log.events[1].payload = "\"not a room\"".to_string();
+        assert!(matches!(replay_event_log(&mut app(), &event_log), Err(ReplayError::InvalidPayload(_))));
+    }
+}
@@ -0,0 +1,189 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
+use bevy_hierarchy::prelude::*;
+use bevy_log::prelude::*;
+use bevy_time::prelude::*;
+
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use crate::{cud_bundle, CudEvent, Identifier, LifetimeClock};
+
+/// Trait for components whose entities give birth to children of bundle `U`, identified by `V`.
+pub trait ReproductiveTrait<U, V> {
+    /// Time between two births.
+    fn get_birth_interval(&self) -> Duration;
+
+    /// Maximum number of children born by the parent that can be alive at the same time.
+    fn get_brood_size(&self) -> usize;
+
+    /// Create the identifier and bundle of a newborn, given the number of children that were born before it.
+    fn give_birth(&self, parent_identifier: &Identifier<V>, births: u32) -> (V, U);
+}
+
+/// A component that keeps track of the children that the entity has given birth to.
+#[derive(Component)]
+pub struct Fertility<V> {
+    pub since_last_birth: Duration,
+    pub births: u32,
+    offspring: Vec<Identifier<V>>,
+}
+
+impl<V> Default for Fertility<V> {
+    fn default() -> Self {
+        Self {
+            since_last_birth: Duration::ZERO,
+            births: 0,
+            offspring: Vec::new(),
+        }
+    }
+}
+
+impl<V> Fertility<V> {
+    /// Get the identifiers of the children born by the entity that were alive at the last birth interval.
+    pub fn get_offspring(&self) -> &[Identifier<V>] {
+        &self.offspring
+    }
+}
+
+/// Plugin that lets the entities with `T` give birth to children of bundle `U`, timed with the clock `C`.
+///
+/// The children are created with `CudEvent::create_child`, so `cud_bundle::<U, V>` must be added to the app.
+pub struct ReproductionPlugin<T, U, V, C = Time> {
+    _marker: PhantomData<(T, U, V, C)>,
+}
+
+impl<T, U, V, C> Default for ReproductionPlugin<T, U, V, C> {
+    fn default() -> Self {
+        Self { _marker: PhantomData }
+    }
+}
+
+impl<T, U, V, C> Plugin for ReproductionPlugin<T, U, V, C>
+where
+    T: Component + ReproductiveTrait<U, V>,
+    U: Bundle + Clone,
+    V: Clone + std::fmt::Debug + PartialEq + Send + Sync + 'static,
+    C: LifetimeClock,
+{
+    /// Build the plugin
+    fn build(&self, app: &mut App) {
+        C::build(app);
+        app.add_systems(Update, (make_fertile::<T, V>, reproduce::<T, U, V, C>).chain().before(cud_bundle::<U, V>));
+    }
+}
+
+/// Inserts a `Fertility` to the entities with `T` that do not have one yet
+pub fn make_fertile<T, V>(mut commands: Commands, queries: Query<Entity, (With<T>, Without<Fertility<V>>)>)
+where
+    T: Component,
+    V: Send + Sync + 'static,
+{
+    for entity in queries.iter() {
+        commands.entity(entity).insert(Fertility::<V>::default());
+    }
+}
+
+/// Sends the events that create the children of the entities with `T`, once their birth interval has passed on the clock `C`
+pub fn reproduce<T, U, V, C>(
+    clock: Res<C>,
+    mut parent_queries: Query<(Entity, &T, &Identifier<V>, &mut Fertility<V>)>,
+    children_queries: Query<&Children>,
+    child_queries: Query<&Identifier<V>>,
+    mut events: EventWriter<CudEvent<U, V>>,
+) where
+    T: Component + ReproductiveTrait<U, V>,
+    U: Bundle + Clone,
+    V: Clone + std::fmt::Debug + PartialEq + Send + Sync + 'static,
+    C: LifetimeClock,
+{
+    for (entity, component, parent_identifier, mut fertility) in parent_queries.iter_mut() {
+        fertility.since_last_birth += clock.delta();
+        if fertility.since_last_birth < component.get_birth_interval() {
+            continue;
+        }
+        fertility.since_last_birth = Duration::ZERO;
+
+        // forget the children that have died since the last birth
+        let living_children: Vec<&Identifier<V>> = children_queries.get(entity).into_iter().flatten().filter_map(|child| child_queries.get(*child).ok()).collect();
+        fertility.offspring.retain(|identifier| living_children.contains(&identifier));
+        if fertility.offspring.len() >= component.get_brood_size() {
+            continue;
+        }
+
+        let (self_identifier, bundle) = component.give_birth(parent_identifier, fertility.births);
+        fertility.births += 1;
+        fertility.offspring.push(Identifier(self_identifier.clone()));
+        debug!("Parent entity {:?} gives birth to child entity {:?}.", parent_identifier, self_identifier);
+        events.send(CudEvent::create_child(parent_identifier.0.clone(), self_identifier, bundle));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{app, entity, identifiers, send, Node};
+    use crate::ManualClock;
+
+    #[derive(Component)]
+    struct Hive;
+
+    impl ReproductiveTrait<Node, String> for Hive {
+        fn get_birth_interval(&self) -> Duration {
+            Duration::from_secs(1)
+        }
+
+        fn get_brood_size(&self) -> usize {
+            2
+        }
+
+        fn give_birth(&self, _: &Identifier<String>, births: u32) -> (String, Node) {
+            (births.to_string(), Node(births))
+        }
+    }
+
+    /// Step the clock by a birth interval for every update.
+    fn run(app: &mut App, updates: usize) {
+        for _ in 0..updates {
+            app.world_mut().resource_mut::<ManualClock>().step(Duration::from_secs(1));
+            app.update();
+        }
+    }
+
+    #[test]
+    fn refills_the_brood_after_a_child_dies() {
+        let mut app = app();
+        app.add_plugins(ReproductionPlugin::<Hive, Node, String, ManualClock>::default());
+        send(&mut app, CudEvent::create_parent("P".to_string(), Node::default()));
+        let parent = entity(&mut app, "P");
+        app.world_mut().entity_mut(parent).insert(Hive);
+
+        run(&mut app, 5);
+        assert_eq!(identifiers(&mut app), ["0", "1", "P"]);
+        assert_eq!(app.world().get::<Fertility<String>>(parent).unwrap().births, 2);
+
+        send(&mut app, CudEvent::delete_child("P".to_string(), "0".to_string(), Node::default()));
+        run(&mut app, 3);
+        assert_eq!(identifiers(&mut app), ["1", "2", "P"]);
+        let newborn = entity(&mut app, "2");
+        assert_eq!(app.world().get::<Node>(newborn), Some(&Node(2)));
+    }
+}
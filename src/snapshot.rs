@@ -0,0 +1,335 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+use std::time::Duration;
+
+use crate::{Action, History, Identifier, Lineage};
+
+/// A node of a `FamilyTreeSnapshot`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SnapshotNode<T> {
+    pub identifier: Identifier<T>,
+    pub parent_identifier: Option<Identifier<T>>,
+    pub generation: u32,
+}
+
+/// The family trees as they were at a point of the `Lineage`, with only the identifiers and the links to the parents
+///
+/// As identifiers are scoped by their parent, a node is keyed by its parent identifier and its identifier.
+/// The histories only link a child to the identifier of its parent, so nodes with the same identifier under different parents share their children.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FamilyTreeSnapshot<T> {
+    pub nodes: Vec<SnapshotNode<T>>,
+}
+
+impl<T> Default for FamilyTreeSnapshot<T> {
+    fn default() -> Self {
+        Self { nodes: Vec::new() }
+    }
+}
+
+impl<T> FamilyTreeSnapshot<T>
+where
+    T: Clone + PartialEq,
+{
+    /// Fold the successful histories into the snapshot, in order.
+    pub fn from_histories<'a>(histories: impl IntoIterator<Item = &'a History<T>>) -> Self
+    where
+        T: 'a,
+    {
+        let mut snapshot = Self::default();
+        for history in histories {
+            snapshot.apply(history);
+        }
+        snapshot
+    }
+
+    /// Apply a successful history to the snapshot, ignoring failed histories.
+    pub fn apply(&mut self, history: &History<T>) {
+        if history.result.is_err() {
+            return;
+        }
+        let (parent_identifier, identifier) = match &history.child_identifier {
+            Some(child_identifier) => (Some(history.parent_identifier.clone()), child_identifier.clone()),
+            None => (None, history.parent_identifier.clone()),
+        };
+        match history.action {
+            Action::Create | Action::CreateWithAncestors | Action::CreateOrModify | Action::Update | Action::Reincarnate(_) => {
+                // an existing entity is respawned without its children
+                self.remove(&parent_identifier, &identifier);
+                self.nodes.push(SnapshotNode {
+                    identifier,
+                    parent_identifier,
+                    generation: history.generation,
+                });
+            }
+            Action::Undo | Action::Redo => {
+                // a generation of 0 means that the entity was removed
+                self.remove(&parent_identifier, &identifier);
+                if history.generation > 0 {
+                    self.nodes.push(SnapshotNode {
+                        identifier,
+                        parent_identifier,
+                        generation: history.generation,
+                    });
+                }
+            }
+            Action::Delete | Action::Expire | Action::Evict => self.remove(&parent_identifier, &identifier),
+            Action::Clear => self.remove_children(&identifier),
+            Action::Touch => {}
+        }
+    }
+
+    /// Get the node by its parent identifier and its identifier.
+    pub fn get(&self, parent_identifier: &Option<Identifier<T>>, identifier: &Identifier<T>) -> Option<&SnapshotNode<T>> {
+        self.nodes.iter().find(|node| node.parent_identifier == *parent_identifier && node.identifier == *identifier)
+    }
+
+    /// Iterate over the nodes without a parent.
+    pub fn roots(&self) -> impl Iterator<Item = &SnapshotNode<T>> {
+        self.nodes.iter().filter(|node| node.parent_identifier.is_none())
+    }
+
+    /// Iterate over the children of the nodes with the identifier.
+    pub fn children<'a>(&'a self, identifier: &'a Identifier<T>) -> impl Iterator<Item = &'a SnapshotNode<T>> {
+        self.nodes.iter().filter(move |node| node.parent_identifier.as_ref() == Some(identifier))
+    }
+
+    /// List what has changed from this snapshot to the other one.
+    ///
+    /// A node that is deleted under one parent and created under another is moved, if its identifier is not deleted or created anywhere else.
+    pub fn diff(&self, other: &Self) -> FamilyTreeDiff<T> {
+        let mut diff = FamilyTreeDiff::default();
+        for node in &self.nodes {
+            match other.get(&node.parent_identifier, &node.identifier) {
+                None => diff.deleted.push(node.clone()),
+                Some(other_node) if other_node.generation != node.generation => diff.updated.push(other_node.clone()),
+                Some(_) => {}
+            }
+        }
+        diff.created = other.nodes.iter().filter(|node| self.get(&node.parent_identifier, &node.identifier).is_none()).cloned().collect();
+
+        let count = |nodes: &[SnapshotNode<T>], identifier: &Identifier<T>| nodes.iter().filter(|node| node.identifier == *identifier).count();
+        let moved: Vec<_> = diff
+            .deleted
+            .iter()
+            .filter(|node| count(&diff.deleted, &node.identifier) == 1 && count(&diff.created, &node.identifier) == 1)
+            .map(|node| MovedNode {
+                identifier: node.identifier.clone(),
+                previous_parent_identifier: node.parent_identifier.clone(),
+                parent_identifier: diff.created.iter().find(|created| created.identifier == node.identifier).and_then(|created| created.parent_identifier.clone()),
+            })
+            .collect();
+        diff.deleted.retain(|node| !moved.iter().any(|moved| moved.identifier == node.identifier));
+        diff.created.retain(|node| !moved.iter().any(|moved| moved.identifier == node.identifier));
+        diff.moved = moved;
+        diff
+    }
+
+    /// Remove the node, and its descendants unless another node with the same identifier still shares them.
+    fn remove(&mut self, parent_identifier: &Option<Identifier<T>>, identifier: &Identifier<T>) {
+        self.nodes.retain(|node| node.parent_identifier != *parent_identifier || node.identifier != *identifier);
+        if !self.nodes.iter().any(|node| node.identifier == *identifier) {
+            self.remove_children(identifier);
+        }
+    }
+
+    /// Remove the children of the nodes with the identifier, and their descendants.
+    fn remove_children(&mut self, identifier: &Identifier<T>) {
+        let parent_identifier = Some(identifier.clone());
+        let children: Vec<_> = self.children(identifier).map(|child| child.identifier.clone()).collect();
+        for child in children {
+            self.remove(&parent_identifier, &child);
+        }
+    }
+}
+
+/// A node that has moved to another parent between two snapshots.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MovedNode<T> {
+    pub identifier: Identifier<T>,
+    pub previous_parent_identifier: Option<Identifier<T>>,
+    pub parent_identifier: Option<Identifier<T>>,
+}
+
+/// Changes between two `FamilyTreeSnapshot`s.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FamilyTreeDiff<T> {
+    pub created: Vec<SnapshotNode<T>>,
+    pub deleted: Vec<SnapshotNode<T>>,
+    pub moved: Vec<MovedNode<T>>,
+    /// Nodes of a new generation under the same parent.
+    pub updated: Vec<SnapshotNode<T>>,
+}
+
+impl<T> Default for FamilyTreeDiff<T> {
+    fn default() -> Self {
+        Self {
+            created: Vec::new(),
+            deleted: Vec::new(),
+            moved: Vec::new(),
+            updated: Vec::new(),
+        }
+    }
+}
+
+impl<T> FamilyTreeDiff<T> {
+    /// Check if nothing has changed.
+    pub fn is_empty(&self) -> bool {
+        self.created.is_empty() && self.deleted.is_empty() && self.moved.is_empty() && self.updated.is_empty()
+    }
+}
+
+impl<T> Lineage<T>
+where
+    T: Clone + PartialEq,
+{
+    /// Reconstruct the family trees from the histories up to the sequence, included.
+    pub fn snapshot_at_sequence(&self, sequence: u64) -> FamilyTreeSnapshot<T> {
+        FamilyTreeSnapshot::from_histories(self.histories.iter().take_while(|history| history.sequence <= sequence))
+    }
+
+    /// Reconstruct the family trees from the histories up to the elapsed time, included.
+    pub fn snapshot_at_time(&self, timestamp: Duration) -> FamilyTreeSnapshot<T> {
+        FamilyTreeSnapshot::from_histories(self.histories.iter().take_while(|history| history.timestamp <= timestamp))
+    }
+
+    /// Reconstruct the family trees from every history.
+    pub fn snapshot(&self) -> FamilyTreeSnapshot<T> {
+        FamilyTreeSnapshot::from_histories(&self.histories)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(identifier: &str) -> Identifier<String> {
+        Identifier(identifier.to_string())
+    }
+
+    fn add(lineage: &mut Lineage<String>, action: Action, parent_identifier: Option<&str>, identifier: &str, generation: u32) {
+        lineage.add_history(History::new(action, parent_identifier.map(id), id(identifier), Ok(())).with_generation(generation));
+    }
+
+    fn keys(snapshot: &FamilyTreeSnapshot<String>) -> Vec<(Option<String>, String)> {
+        let mut keys: Vec<_> = snapshot.nodes.iter().map(|node| (node.parent_identifier.as_ref().map(|parent| parent.0.clone()), node.identifier.0.clone())).collect();
+        keys.sort();
+        keys
+    }
+
+    fn key(parent_identifier: Option<&str>, identifier: &str) -> (Option<String>, String) {
+        (parent_identifier.map(str::to_string), identifier.to_string())
+    }
+
+    #[test]
+    fn keeps_identifiers_scoped_by_parent() {
+        let mut lineage = Lineage::default();
+        add(&mut lineage, Action::Create, None, "B", 1);
+        add(&mut lineage, Action::Create, Some("B"), "L1", 1);
+        add(&mut lineage, Action::Create, Some("B"), "L2", 1);
+        add(&mut lineage, Action::Create, Some("L1"), "R", 1);
+        add(&mut lineage, Action::Create, Some("L2"), "R", 1);
+
+        let snapshot = lineage.snapshot();
+        assert_eq!(snapshot.nodes.len(), 5);
+        assert!(snapshot.get(&Some(id("L1")), &id("R")).is_some());
+        assert!(snapshot.get(&Some(id("L2")), &id("R")).is_some());
+    }
+
+    #[test]
+    fn folds_up_to_the_sequence() {
+        let mut lineage = Lineage::default();
+        add(&mut lineage, Action::Create, None, "A", 1);
+        add(&mut lineage, Action::Create, Some("A"), "B", 1);
+        add(&mut lineage, Action::Create, Some("B"), "C", 1);
+        lineage.add_history(History::new(Action::Create, None, id("A"), Err(crate::FamilyError::AlreadyExists)));
+        add(&mut lineage, Action::Delete, Some("A"), "B", 1);
+
+        assert_eq!(keys(&lineage.snapshot_at_sequence(0)), [key(None, "A")]);
+        assert_eq!(keys(&lineage.snapshot_at_sequence(3)), [key(None, "A"), key(Some("A"), "B"), key(Some("B"), "C")]);
+        assert_eq!(keys(&lineage.snapshot()), [key(None, "A")]);
+    }
+
+    #[test]
+    fn clear_and_undo_remove_descendants() {
+        let mut lineage = Lineage::default();
+        add(&mut lineage, Action::Create, None, "A", 1);
+        add(&mut lineage, Action::Create, Some("A"), "B", 1);
+        add(&mut lineage, Action::Create, Some("B"), "C", 1);
+        add(&mut lineage, Action::Clear, None, "A", 1);
+        assert_eq!(keys(&lineage.snapshot()), [key(None, "A")]);
+
+        add(&mut lineage, Action::Create, Some("A"), "B", 2);
+        add(&mut lineage, Action::Undo, Some("A"), "B", 0);
+        assert_eq!(keys(&lineage.snapshot()), [key(None, "A")]);
+        add(&mut lineage, Action::Redo, Some("A"), "B", 3);
+        assert_eq!(lineage.snapshot().get(&Some(id("A")), &id("B")).unwrap().generation, 3);
+    }
+
+    #[test]
+    fn removing_a_shared_identifier_keeps_the_children_of_the_other() {
+        let mut lineage = Lineage::default();
+        add(&mut lineage, Action::Create, None, "L1", 1);
+        add(&mut lineage, Action::Create, None, "L2", 1);
+        add(&mut lineage, Action::Create, Some("L1"), "R", 1);
+        add(&mut lineage, Action::Create, Some("L2"), "R", 1);
+        add(&mut lineage, Action::Create, Some("R"), "X", 1);
+        add(&mut lineage, Action::Delete, Some("L2"), "R", 1);
+        assert_eq!(keys(&lineage.snapshot()), [key(None, "L1"), key(None, "L2"), key(Some("L1"), "R"), key(Some("R"), "X")]);
+
+        add(&mut lineage, Action::Delete, Some("L1"), "R", 1);
+        assert_eq!(keys(&lineage.snapshot()), [key(None, "L1"), key(None, "L2")]);
+    }
+
+    #[test]
+    fn diff_lists_created_deleted_moved_and_updated() {
+        let mut lineage = Lineage::default();
+        add(&mut lineage, Action::Create, None, "A", 1);
+        add(&mut lineage, Action::Create, None, "B", 1);
+        add(&mut lineage, Action::Create, Some("A"), "C", 1);
+        add(&mut lineage, Action::Create, Some("A"), "D", 1);
+        add(&mut lineage, Action::Create, Some("A"), "R", 1);
+        add(&mut lineage, Action::Create, Some("B"), "R", 1);
+        let before = lineage.snapshot();
+
+        add(&mut lineage, Action::Delete, Some("A"), "C", 1);
+        add(&mut lineage, Action::Create, Some("B"), "C", 1);
+        add(&mut lineage, Action::Update, Some("A"), "D", 2);
+        add(&mut lineage, Action::Delete, Some("A"), "R", 1);
+        add(&mut lineage, Action::Create, Some("B"), "E", 1);
+        let diff = before.diff(&lineage.snapshot());
+
+        assert_eq!(
+            diff.moved,
+            [MovedNode {
+                identifier: id("C"),
+                previous_parent_identifier: Some(id("A")),
+                parent_identifier: Some(id("B")),
+            }]
+        );
+        assert_eq!(diff.updated.iter().map(|node| (node.identifier.0.as_str(), node.generation)).collect::<Vec<_>>(), [("D", 2)]);
+        assert_eq!(diff.deleted.iter().map(|node| (node.parent_identifier.clone(), node.identifier.clone())).collect::<Vec<_>>(), [(Some(id("A")), id("R"))]);
+        assert_eq!(diff.created.iter().map(|node| (node.parent_identifier.clone(), node.identifier.clone())).collect::<Vec<_>>(), [(Some(id("B")), id("E"))]);
+        assert!(lineage.snapshot().diff(&lineage.snapshot()).is_empty());
+    }
+}
@@ -0,0 +1,246 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
+use bevy_ecs::schedule::{InternedScheduleLabel, ScheduleLabel};
+use bevy_log::prelude::*;
+
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use crate::{BiologicalClock, BiologicalTrait, LifetimeSystem};
+
+/// Trait for components that go through stages in their life.
+pub trait LifeStageTrait: BiologicalTrait {
+    /// Stages of the entity's life, in the order that they are reached.
+    fn get_life_stages(&self) -> Vec<LifeStage>;
+}
+
+/// Point in an entity's life at which a stage begins.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StageThreshold {
+    /// Fraction of the lifespan, where `0.0` is birth and `1.0` is the end of the lifespan.
+    Fraction(f32),
+    /// Age of the entity on the lifetime clock.
+    Age(Duration),
+}
+
+/// A named stage of an entity's life.
+#[derive(Clone, Debug)]
+pub struct LifeStage {
+    pub name: &'static str,
+    pub threshold: StageThreshold,
+    marker: Option<StageMarker>,
+}
+
+/// Functions that insert and remove the marker component of a stage.
+#[derive(Clone, Copy, Debug)]
+struct StageMarker {
+    insert: fn(&mut EntityCommands),
+    remove: fn(&mut EntityCommands),
+}
+
+impl LifeStage {
+    /// Create a stage that begins at a fraction of the lifespan.
+    pub fn at_fraction(name: &'static str, fraction: f32) -> Self {
+        Self {
+            name,
+            threshold: StageThreshold::Fraction(fraction),
+            marker: None,
+        }
+    }
+
+    /// Create a stage that begins at an age.
+    pub fn at_age(name: &'static str, age: Duration) -> Self {
+        Self {
+            name,
+            threshold: StageThreshold::Age(age),
+            marker: None,
+        }
+    }
+
+    /// Insert the marker component `M` while the entity is in this stage.
+    pub fn with_marker<M>(mut self) -> Self
+    where
+        M: Component + Default,
+    {
+        self.marker = Some(StageMarker {
+            insert: |entity_commands| {
+                entity_commands.insert(M::default());
+            },
+            remove: |entity_commands| {
+                entity_commands.remove::<M>();
+            },
+        });
+        self
+    }
+
+    /// Check if the stage has begun, given the progress through the lifespan and the clock.
+    fn has_begun(&self, progress: Option<f32>, clock: &BiologicalClock) -> bool {
+        match self.threshold {
+            StageThreshold::Fraction(fraction) => progress.is_some_and(|progress| fraction <= progress),
+            StageThreshold::Age(age) => age <= clock.age(),
+        }
+    }
+}
+
+/// A component that holds the current life stage of the entity.
+#[derive(Component, Clone, Debug, PartialEq)]
+pub struct CurrentLifeStage {
+    pub name: &'static str,
+    index: usize,
+}
+
+/// Event that is sent when an entity of type `T` enters a new life stage
+#[derive(Event)]
+pub struct LifeStageChanged<T> {
+    pub entity: Entity,
+    pub previous: Option<&'static str>,
+    pub current: &'static str,
+    _marker: PhantomData<T>,
+}
+
+/// Plugin that moves the entities of type `T` through their life stages.
+///
+/// The stages are evaluated before the entities are aged, so a stage at `StageThreshold::Fraction(1.0)` is entered
+/// on the last update before the entity expires.
+pub struct LifeStagePlugin<T> {
+    schedule: InternedScheduleLabel,
+    _marker: PhantomData<T>,
+}
+
+impl<T> Default for LifeStagePlugin<T> {
+    fn default() -> Self {
+        Self::new(Update)
+    }
+}
+
+impl<T> LifeStagePlugin<T> {
+    /// Create the plugin that runs the life stage system in the given schedule.
+    pub fn new(schedule: impl ScheduleLabel) -> Self {
+        Self {
+            schedule: schedule.intern(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Plugin for LifeStagePlugin<T>
+where
+    T: Component + LifeStageTrait,
+{
+    /// Build the plugin
+    fn build(&self, app: &mut App) {
+        app.add_event::<LifeStageChanged<T>>();
+        app.add_systems(self.schedule, update_life_stages::<T>.before(LifetimeSystem));
+    }
+}
+
+/// Moves the entities into the latest stage that they have reached, swapping the stage markers
+pub fn update_life_stages<T>(mut commands: Commands, queries: Query<(Entity, &BiologicalClock, &T, Option<&CurrentLifeStage>)>, mut events: EventWriter<LifeStageChanged<T>>)
+where
+    T: Component + LifeStageTrait,
+{
+    for (entity, biological_clock, component, current_stage) in queries.iter() {
+        let stages = component.get_life_stages();
+        let progress = component.get_lifespan().progress_of(biological_clock);
+        let Some(index) = stages.iter().rposition(|stage| stage.has_begun(progress, biological_clock)) else {
+            continue;
+        };
+        if current_stage.is_some_and(|current_stage| current_stage.index == index) {
+            continue;
+        }
+
+        let mut entity_commands = commands.entity(entity);
+        if let Some(marker) = current_stage.and_then(|current_stage| stages.get(current_stage.index)).and_then(|stage| stage.marker) {
+            (marker.remove)(&mut entity_commands);
+        }
+        if let Some(marker) = stages[index].marker {
+            (marker.insert)(&mut entity_commands);
+        }
+        entity_commands.insert(CurrentLifeStage { name: stages[index].name, index });
+
+        debug!("Entity {:?} entered life stage {:?}.", entity, stages[index].name);
+        events.send(LifeStageChanged {
+            entity,
+            previous: current_stage.map(|current_stage| current_stage.name),
+            current: stages[index].name,
+            _marker: PhantomData,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LifetimePlugin, ManualClock};
+    use bevy_ecs::event::EventCursor;
+
+    #[derive(Component)]
+    struct Mayfly;
+
+    impl BiologicalTrait for Mayfly {
+        fn get_lifetime(&self) -> Duration {
+            Duration::from_secs(10)
+        }
+    }
+
+    impl LifeStageTrait for Mayfly {
+        fn get_life_stages(&self) -> Vec<LifeStage> {
+            vec![
+                LifeStage::at_age("Nymph", Duration::ZERO).with_marker::<Nymph>(),
+                LifeStage::at_fraction("Adult", 0.5).with_marker::<Adult>(),
+            ]
+        }
+    }
+
+    #[derive(Component, Default)]
+    struct Nymph;
+
+    #[derive(Component, Default)]
+    struct Adult;
+
+    /// Step the clock, run two updates and get the stage changes that were sent.
+    fn step(app: &mut App, delta: Duration, cursor: &mut EventCursor<LifeStageChanged<Mayfly>>) -> Vec<(Option<&'static str>, &'static str)> {
+        app.world_mut().resource_mut::<ManualClock>().step(delta);
+        app.update();
+        app.update();
+        let events = app.world().resource::<Events<LifeStageChanged<Mayfly>>>();
+        cursor.read(events).map(|event| (event.previous, event.current)).collect()
+    }
+
+    #[test]
+    fn swaps_the_markers_as_the_stages_change() {
+        let mut app = App::new();
+        app.add_plugins((LifetimePlugin::<Mayfly, ManualClock>::default(), LifeStagePlugin::<Mayfly>::default()));
+        let entity = app.world_mut().spawn(Mayfly).id();
+        let mut cursor = EventCursor::default();
+
+        assert_eq!(step(&mut app, Duration::from_secs(1), &mut cursor), [(None, "Nymph")]);
+        assert_eq!(app.world().get::<CurrentLifeStage>(entity).map(|stage| stage.name), Some("Nymph"));
+        assert!(app.world().entity(entity).contains::<Nymph>());
+
+        assert_eq!(step(&mut app, Duration::from_secs(5), &mut cursor), [(Some("Nymph"), "Adult")]);
+        assert_eq!(app.world().get::<CurrentLifeStage>(entity).map(|stage| stage.name), Some("Adult"));
+        assert!(!app.world().entity(entity).contains::<Nymph>());
+        assert!(app.world().entity(entity).contains::<Adult>());
+
+        assert_eq!(step(&mut app, Duration::from_secs(1), &mut cursor), []);
+    }
+}
@@ -0,0 +1,61 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
+use bevy_reflect::prelude::*;
+
+use crate::{cud_bundle, CudEvent, FamilyPlugin, Identifier};
+
+/// Bundle of the entities of the tests, with a value to check that it is kept.
+#[derive(Component, Reflect, Clone, Debug, Default, PartialEq)]
+#[reflect(Component)]
+pub struct Node(pub u32);
+
+/// Create the app that handles the `CudEvent`s of `Node` with string identifiers.
+pub fn app() -> App {
+    let mut app = App::new();
+    app.add_plugins(FamilyPlugin::<String>::default());
+    app.register_type::<Node>();
+    app.add_event::<CudEvent<Node, String>>();
+    app.add_systems(Update, cud_bundle::<Node, String>);
+    app
+}
+
+/// Send the event and handle it in an update.
+pub fn send(app: &mut App, event: CudEvent<Node, String>) {
+    app.world_mut().send_event(event);
+    app.update();
+}
+
+/// Find the entity with the identifier, wherever it is in the trees.
+pub fn entity(app: &mut App, identifier: &str) -> Entity {
+    app.world_mut()
+        .query::<(Entity, &Identifier<String>)>()
+        .iter(app.world())
+        .find(|(_, id)| id.0 == identifier)
+        .map(|(entity, _)| entity)
+        .unwrap()
+}
+
+/// Get the identifiers of every entity, sorted.
+pub fn identifiers(app: &mut App) -> Vec<String> {
+    let mut identifiers: Vec<String> = app.world_mut().query::<&Identifier<String>>().iter(app.world()).map(|identifier| identifier.0.clone()).collect();
+    identifiers.sort();
+    identifiers
+}
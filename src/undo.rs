@@ -0,0 +1,632 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
+use bevy_ecs::reflect::{AppTypeRegistry, ReflectComponent};
+use bevy_hierarchy::prelude::*;
+use bevy_log::prelude::*;
+use bevy_reflect::PartialReflect;
+
+use std::any::TypeId;
+use std::marker::PhantomData;
+
+use crate::{Action, BiologicalClock, FamilyDepth, FamilyMember, FamilyPath, Generation, Generations, History, Identifier, Lineage};
+
+/// State of an entity and its descendants, to bring them back when an operation is undone or redone.
+///
+/// Components are copied through reflection, so only the components that are registered with `#[reflect(Component)]` are kept,
+/// along with the identifier, the `BiologicalClock` and the `FamilyMember` markers of the bundles that the undo history has seen.
+pub struct EntitySnapshot<V> {
+    pub identifier: Option<Identifier<V>>,
+    pub children: Vec<EntitySnapshot<V>>,
+    components: Vec<(ReflectComponent, Box<dyn PartialReflect>)>,
+    biological_clock: Option<BiologicalClock>,
+    members: Vec<fn(&mut EntityWorldMut)>,
+}
+
+/// The `FamilyMember` marker of a bundle, with the function that inserts it.
+type MemberMarker = (TypeId, fn(&mut EntityWorldMut));
+
+/// The parent identifier, identifier and generation of an entity spawned from a snapshot.
+type Spawned<V> = (Option<Identifier<V>>, Identifier<V>, u32);
+
+impl<V> EntitySnapshot<V>
+where
+    V: Clone + Send + Sync + 'static,
+{
+    /// Take the snapshot of the entity and its descendants.
+    fn capture(world: &World, entity: Entity, markers: &[MemberMarker]) -> Self {
+        let entity_ref = world.entity(entity);
+        let is_member = markers.iter().any(|(type_id, _)| entity_ref.contains_type_id(*type_id));
+        let mut components = Vec::new();
+        if let Some(type_registry) = world.get_resource::<AppTypeRegistry>() {
+            let type_registry = type_registry.read();
+            for component_id in entity_ref.archetype().components() {
+                let Some(info) = world.components().get_info(component_id) else {
+                    continue;
+                };
+                let Some(type_id) = info.type_id() else {
+                    continue;
+                };
+                // the hierarchy is rebuilt from the snapshot
+                if type_id == TypeId::of::<Parent>() || type_id == TypeId::of::<Children>() {
+                    continue;
+                }
+                let Some(reflect_component) = type_registry.get_type_data::<ReflectComponent>(type_id) else {
+                    if is_member && !Self::is_kept(type_id, markers) {
+                        warn!("Component {} of entity {:?} is not reflected, it is not kept by the undo history.", info.name(), entity);
+                    }
+                    continue;
+                };
+                if let Some(component) = reflect_component.reflect(entity_ref) {
+                    components.push((reflect_component.clone(), component.clone_value()));
+                }
+            }
+        }
+        Self {
+            identifier: entity_ref.get::<Identifier<V>>().cloned(),
+            children: entity_ref.get::<Children>().into_iter().flatten().map(|child| Self::capture(world, *child, markers)).collect(),
+            components,
+            biological_clock: entity_ref.get::<BiologicalClock>().map(BiologicalClock::frozen),
+            members: markers.iter().filter(|(type_id, _)| entity_ref.contains_type_id(*type_id)).map(|(_, insert)| *insert).collect(),
+        }
+    }
+
+    /// Whether the component is kept by the snapshot without reflection, or rebuilt by the `FamilyPlugin`.
+    fn is_kept(type_id: TypeId, markers: &[MemberMarker]) -> bool {
+        type_id == TypeId::of::<Identifier<V>>()
+            || type_id == TypeId::of::<Generation>()
+            || type_id == TypeId::of::<BiologicalClock>()
+            || type_id == TypeId::of::<FamilyDepth>()
+            || markers.iter().any(|(marker, _)| *marker == type_id)
+    }
+
+    /// Spawn the entity and its descendants from the snapshot, as a new generation of their identifiers.
+    ///
+    /// The parent identifier, identifier and generation of every spawned entity with an identifier are pushed to `spawned`, parents first.
+    fn spawn(&self, world: &mut World, parent_identifier: Option<Identifier<V>>, spawned: &mut Vec<Spawned<V>>) -> Entity
+    where
+        V: PartialEq,
+    {
+        let mut entity = world.spawn_empty();
+        if let Some(type_registry) = entity.world().get_resource::<AppTypeRegistry>().cloned() {
+            let type_registry = type_registry.read();
+            for (reflect_component, component) in &self.components {
+                reflect_component.insert(&mut entity, component.as_ref(), &type_registry);
+            }
+        }
+        if let Some(biological_clock) = &self.biological_clock {
+            entity.insert(biological_clock.frozen());
+        }
+        for member in &self.members {
+            member(&mut entity);
+        }
+        let entity = entity.id();
+        if let Some(identifier) = &self.identifier {
+            let generation = world.resource_mut::<Generations<V>>().next(&parent_identifier, identifier);
+            world.entity_mut(entity).insert((identifier.clone(), Generation(generation)));
+            spawned.push((parent_identifier, identifier.clone(), generation));
+        }
+        for child in &self.children {
+            let child = child.spawn(world, self.identifier.clone(), spawned);
+            world.entity_mut(entity).add_child(child);
+        }
+        entity
+    }
+}
+
+/// Insert the `FamilyMember` marker of the bundle `U`
+fn insert_member<U>(entity: &mut EntityWorldMut)
+where
+    U: Bundle,
+{
+    entity.insert(FamilyMember::<U>::default());
+}
+
+/// An operation of `cud_bundle` with the state at its path before and after it.
+pub struct UndoOperation<V> {
+    pub before: Option<(FamilyPath<V>, EntitySnapshot<V>)>,
+    pub after: Option<(FamilyPath<V>, EntitySnapshot<V>)>,
+}
+
+/// Resource with the operations of `cud_bundle` on the identifier `V` that can be undone and redone
+///
+/// Every successful event is a step of its own, unless it happens between `begin_group` and `end_group`.
+#[derive(Resource)]
+pub struct FamilyUndo<V> {
+    undo_steps: Vec<Vec<UndoOperation<V>>>,
+    redo_steps: Vec<Vec<UndoOperation<V>>>,
+    group: Option<Vec<UndoOperation<V>>>,
+    pending: Option<(FamilyPath<V>, EntitySnapshot<V>)>,
+    /// Evictions and missing ancestors of the event that is being handled, undone along with it.
+    pending_operations: Vec<UndoOperation<V>>,
+    markers: Vec<MemberMarker>,
+    max_steps: usize,
+}
+
+impl<V> FamilyUndo<V> {
+    /// Create the undo history that keeps up to the number of steps.
+    pub fn new(max_steps: usize) -> Self {
+        Self {
+            undo_steps: Vec::new(),
+            redo_steps: Vec::new(),
+            group: None,
+            pending: None,
+            pending_operations: Vec::new(),
+            markers: Vec::new(),
+            max_steps,
+        }
+    }
+
+    /// Start grouping the next operations into a single step.
+    pub fn begin_group(&mut self) {
+        if self.group.is_none() {
+            self.group = Some(Vec::new());
+        }
+    }
+
+    /// Stop grouping operations, turning the grouped operations into a single step.
+    pub fn end_group(&mut self) {
+        if let Some(group) = self.group.take() {
+            if !group.is_empty() {
+                self.push_step(group);
+            }
+        }
+    }
+
+    /// Number of steps that can be undone.
+    pub fn undo_len(&self) -> usize {
+        self.undo_steps.len()
+    }
+
+    /// Number of steps that can be redone.
+    pub fn redo_len(&self) -> usize {
+        self.redo_steps.len()
+    }
+
+    /// Forget every step.
+    pub fn clear(&mut self) {
+        self.undo_steps.clear();
+        self.redo_steps.clear();
+        self.group = None;
+    }
+
+    /// Add the operations of an event, as a step or to the current group.
+    fn push(&mut self, operations: Vec<UndoOperation<V>>) {
+        match &mut self.group {
+            Some(group) => group.extend(operations),
+            None => self.push_step(operations),
+        }
+    }
+
+    /// Keep the `FamilyMember` marker of the bundle `U` in the snapshots.
+    fn add_marker<U>(&mut self)
+    where
+        U: Bundle,
+    {
+        let type_id = TypeId::of::<FamilyMember<U>>();
+        if !self.markers.iter().any(|(marker, _)| *marker == type_id) {
+            self.markers.push((type_id, insert_member::<U>));
+        }
+    }
+
+    /// Add a step, forgetting the steps that were undone and the oldest steps over the maximum.
+    fn push_step(&mut self, step: Vec<UndoOperation<V>>) {
+        self.redo_steps.clear();
+        self.undo_steps.push(step);
+        if self.undo_steps.len() > self.max_steps {
+            let excess = self.undo_steps.len() - self.max_steps;
+            self.undo_steps.drain(..excess);
+        }
+    }
+}
+
+/// Event that undoes or redoes the last step of the `FamilyUndo` of the identifier `V`
+#[derive(Event)]
+pub struct FamilyUndoEvent<V> {
+    redo: bool,
+    _marker: PhantomData<V>,
+}
+
+impl<V> FamilyUndoEvent<V> {
+    pub fn undo() -> Self {
+        Self { redo: false, _marker: PhantomData }
+    }
+
+    pub fn redo() -> Self {
+        Self { redo: true, _marker: PhantomData }
+    }
+
+    pub fn is_redo(&self) -> bool {
+        self.redo
+    }
+}
+
+/// Plugin that keeps the operations of `cud_bundle` on the identifier `V` in a `FamilyUndo`, to undo and redo them with `FamilyUndoEvent`s
+pub struct FamilyUndoPlugin<V> {
+    max_steps: usize,
+    _marker: PhantomData<V>,
+}
+
+impl<V> Default for FamilyUndoPlugin<V> {
+    fn default() -> Self {
+        Self {
+            max_steps: 100,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<V> FamilyUndoPlugin<V> {
+    /// Keep up to the number of steps, forgetting the oldest ones.
+    pub fn with_max_steps(mut self, max_steps: usize) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+}
+
+impl<V> Plugin for FamilyUndoPlugin<V>
+where
+    V: Clone + std::fmt::Debug + PartialEq + Default + Send + Sync + 'static,
+{
+    /// Build the plugin
+    fn build(&self, app: &mut App) {
+        app.insert_resource(FamilyUndo::<V>::new(self.max_steps));
+        app.add_event::<FamilyUndoEvent<V>>();
+        app.add_systems(PreUpdate, undo_or_redo::<V>);
+    }
+}
+
+/// Undoes or redoes the steps of the undo events
+pub fn undo_or_redo<V>(mut commands: Commands, mut events: EventReader<FamilyUndoEvent<V>>)
+where
+    V: Clone + std::fmt::Debug + PartialEq + Default + Send + Sync + 'static,
+{
+    for event in events.read() {
+        let redo = event.is_redo();
+        commands.queue(move |world: &mut World| apply_step::<V>(world, redo));
+    }
+}
+
+/// Undo the last step, or redo the last undone step
+fn apply_step<V>(world: &mut World, redo: bool)
+where
+    V: Clone + std::fmt::Debug + PartialEq + Default + Send + Sync + 'static,
+{
+    world.resource_scope::<FamilyUndo<V>, _>(|world, mut undo| {
+        let steps = if redo { &mut undo.redo_steps } else { &mut undo.undo_steps };
+        let Some(step) = steps.pop() else {
+            debug!("Nothing to {}.", if redo { "redo" } else { "undo" });
+            return;
+        };
+        if redo {
+            for operation in &step {
+                restore(world, operation.before.as_ref(), operation.after.as_ref(), Action::Redo);
+            }
+            undo.undo_steps.push(step);
+        } else {
+            for operation in step.iter().rev() {
+                restore(world, operation.after.as_ref(), operation.before.as_ref(), Action::Undo);
+            }
+            undo.redo_steps.push(step);
+        }
+    });
+}
+
+/// Replace the entity at the path of the current state with the entity of the restored state
+fn restore<V>(world: &mut World, current: Option<&(FamilyPath<V>, EntitySnapshot<V>)>, restored: Option<&(FamilyPath<V>, EntitySnapshot<V>)>, action: Action)
+where
+    V: Clone + std::fmt::Debug + PartialEq + Default + Send + Sync + 'static,
+{
+    if let Some((path, _)) = current {
+        if let Some(entity) = find_by_path(world, path) {
+            world.entity_mut(entity).despawn_recursive();
+        }
+    }
+    let histories = match restored {
+        Some((path, snapshot)) => {
+            let parent = match path.parent() {
+                Some(parent_path) => match find_by_path(world, &parent_path) {
+                    Some(parent) => Some(parent),
+                    None => {
+                        warn!("Parent of entity {:?} does not exist to restore it.", path.leaf());
+                        return;
+                    }
+                },
+                None => None,
+            };
+            // the descendants are restored along with the entity, each as a new generation
+            let mut spawned = Vec::new();
+            let entity = snapshot.spawn(world, path.parent_identifier().cloned(), &mut spawned);
+            if let Some(parent) = parent {
+                world.entity_mut(parent).add_child(entity);
+            }
+            spawned
+        }
+        None => match current {
+            Some((path, _)) => vec![(path.parent_identifier().cloned(), path.leaf().clone(), 0)],
+            None => return,
+        },
+    };
+    let mut lineage = world.resource_mut::<Lineage<V>>();
+    for (parent_identifier, identifier, generation) in histories {
+        debug!("Entity {:?} is restored by {:?}.", identifier, action);
+        lineage.add_history(History::new(action.clone(), parent_identifier, identifier, Ok(())).with_generation(generation));
+    }
+}
+
+/// Find the entity by its path in the world
+fn find_by_path<V>(world: &mut World, path: &FamilyPath<V>) -> Option<Entity>
+where
+    V: PartialEq + Send + Sync + 'static,
+{
+    let mut identifiers = world.query::<(Entity, &Identifier<V>, Option<&Parent>)>();
+    let mut parent: Option<Entity> = None;
+    for identifier in path.identifiers() {
+        let entity = identifiers
+            .iter(world)
+            .find(|(_, id, entity_parent)| *id == identifier && entity_parent.map(|entity_parent| entity_parent.get()) == parent)
+            .map(|(entity, _, _)| entity)?;
+        parent = Some(entity);
+    }
+    parent
+}
+
+/// Find the path of the entity in the world
+fn path_of<V>(world: &World, entity: Entity) -> Option<FamilyPath<V>>
+where
+    V: Clone + Send + Sync + 'static,
+{
+    let mut identifiers = vec![world.get::<Identifier<V>>(entity)?.clone()];
+    let mut current = entity;
+    while let Some(parent) = world.get::<Parent>(current) {
+        current = parent.get();
+        match world.get::<Identifier<V>>(current) {
+            Some(identifier) => identifiers.push(identifier.clone()),
+            None => break,
+        }
+    }
+    identifiers.reverse();
+    FamilyPath::from_identifiers(identifiers)
+}
+
+/// Find the entity of an event in the world, as `cud_bundle` does, by its path or else by its identifier
+fn find_target<V>(world: &mut World, path: Option<&FamilyPath<V>>, identifier: &Identifier<V>) -> Option<Entity>
+where
+    V: Clone + PartialEq + Send + Sync + 'static,
+{
+    match path {
+        Some(path) => find_by_path(world, path),
+        None => world.query::<(Entity, &Identifier<V>)>().iter(world).find(|(_, id)| *id == identifier).map(|(entity, _)| entity),
+    }
+}
+
+/// Take the snapshot of the entity with its path, with the `FamilyMember` markers that the undo history has seen
+fn snapshot<V>(world: &World, entity: Entity) -> Option<(FamilyPath<V>, EntitySnapshot<V>)>
+where
+    V: Clone + Send + Sync + 'static,
+{
+    let markers = world.get_resource::<FamilyUndo<V>>().map_or(&[][..], |undo| &undo.markers);
+    Some((path_of(world, entity)?, EntitySnapshot::capture(world, entity, markers)))
+}
+
+/// Make the `FamilyMember` marker of the bundle `U` known to the undo history, if there is one
+pub(crate) fn register_member<U, V>(mut entity: EntityWorldMut)
+where
+    U: Bundle,
+    V: Send + Sync + 'static,
+{
+    entity.world_scope(|world| {
+        if let Some(mut undo) = world.get_resource_mut::<FamilyUndo<V>>() {
+            undo.add_marker::<U>();
+        }
+    });
+}
+
+/// Keep the state of the entity of an event before it is handled
+pub(crate) fn capture_before<U, V>(world: &mut World, path: Option<FamilyPath<V>>, identifier: Identifier<V>)
+where
+    U: Bundle,
+    V: Clone + PartialEq + Send + Sync + 'static,
+{
+    if let Some(mut undo) = world.get_resource_mut::<FamilyUndo<V>>() {
+        undo.add_marker::<U>();
+        undo.pending_operations.clear();
+    }
+    let before = find_target(world, path.as_ref(), &identifier).and_then(|entity| snapshot(world, entity));
+    if let Some(mut undo) = world.get_resource_mut::<FamilyUndo<V>>() {
+        undo.pending = before;
+    }
+}
+
+/// Keep the state of an entity that is evicted for an event, before it is despawned
+pub(crate) fn capture_evicted<V>(world: &mut World, entity: Entity)
+where
+    V: Clone + Send + Sync + 'static,
+{
+    if !world.contains_resource::<FamilyUndo<V>>() {
+        return;
+    }
+    let before = snapshot(world, entity);
+    if before.is_some() {
+        world.resource_mut::<FamilyUndo<V>>().pending_operations.push(UndoOperation { before, after: None });
+    }
+}
+
+/// Keep the state of a missing ancestor that is spawned for an event, before its children are added
+pub(crate) fn capture_ancestor<V>(world: &mut World, entity: Entity)
+where
+    V: Clone + Send + Sync + 'static,
+{
+    if !world.contains_resource::<FamilyUndo<V>>() {
+        return;
+    }
+    let after = snapshot(world, entity);
+    if after.is_some() {
+        world.resource_mut::<FamilyUndo<V>>().pending_operations.push(UndoOperation { before: None, after });
+    }
+}
+
+/// Keep the state of the entity of a successful event after it is handled, as a step of the undo history along with its evictions and missing ancestors
+pub(crate) fn capture_after<V>(world: &mut World, action: Action, path: Option<FamilyPath<V>>, parent_identifier: Option<Identifier<V>>, identifier: Identifier<V>)
+where
+    V: Clone + PartialEq + Send + Sync + 'static,
+{
+    let Some((before, mut operations)) = world.get_resource_mut::<FamilyUndo<V>>().map(|mut undo| (undo.pending.take(), std::mem::take(&mut undo.pending_operations))) else {
+        return;
+    };
+    let entity = match action {
+        Action::Delete => None,
+        Action::Clear => before.as_ref().and_then(|(path, _)| find_by_path(world, path)),
+        _ => match (path, parent_identifier) {
+            (Some(path), _) => find_by_path(world, &path),
+            (None, Some(parent_identifier)) => find_target(world, None, &parent_identifier).and_then(|parent| {
+                world.get::<Children>(parent)?.iter().copied().find(|child| world.get::<Identifier<V>>(*child) == Some(&identifier))
+            }),
+            (None, None) => find_target(world, None, &identifier),
+        },
+    };
+    let after = entity.and_then(|entity| snapshot(world, entity));
+    if before.is_some() || after.is_some() {
+        operations.push(UndoOperation { before, after });
+    }
+    if !operations.is_empty() {
+        world.resource_mut::<FamilyUndo<V>>().push(operations);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{send, Node};
+    use crate::{AncestorBundles, CapPolicy, CudEvent, PopulationCap};
+
+    fn app() -> App {
+        let mut app = crate::testing::app();
+        app.add_plugins(FamilyUndoPlugin::<String>::default());
+        app
+    }
+
+    fn apply(app: &mut App, event: FamilyUndoEvent<String>) {
+        app.world_mut().send_event(event);
+        app.update();
+    }
+
+    /// Get the paths of the entities, with whether they are marked as members of `Node`.
+    fn paths(app: &mut App) -> Vec<(String, bool)> {
+        let entities: Vec<Entity> = app.world_mut().query_filtered::<Entity, With<Identifier<String>>>().iter(app.world()).collect();
+        let mut paths: Vec<(String, bool)> = entities
+            .into_iter()
+            .map(|entity| (path_of::<String>(app.world(), entity).unwrap().to_string(), app.world().entity(entity).contains::<FamilyMember<Node>>()))
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    fn entries(entries: &[(&str, bool)]) -> Vec<(String, bool)> {
+        entries.iter().map(|(path, member)| (path.to_string(), *member)).collect()
+    }
+
+    #[test]
+    fn undoes_the_missing_ancestors_with_the_entity() {
+        let mut app = app();
+        app.insert_resource(AncestorBundles::<String>::default().with_bundle(0, Node::default()));
+        send(&mut app, CudEvent::create_with_ancestors("R/a/b".parse().unwrap(), Node::default()));
+        let created = entries(&[("R", true), ("R/a", false), ("R/a/b", true)]);
+        assert_eq!(paths(&mut app), created);
+        assert_eq!(app.world().resource::<FamilyUndo<String>>().undo_len(), 1);
+
+        apply(&mut app, FamilyUndoEvent::undo());
+        assert_eq!(paths(&mut app), []);
+
+        apply(&mut app, FamilyUndoEvent::redo());
+        assert_eq!(paths(&mut app), created);
+    }
+
+    #[test]
+    fn undo_brings_back_the_evicted_entity() {
+        let mut app = app();
+        app.insert_resource(PopulationCap::<Node>::default().with_per_type(1).with_policy(CapPolicy::EvictOldest));
+        send(&mut app, CudEvent::create_parent("A".to_string(), Node::default()));
+        send(&mut app, CudEvent::create_parent("B".to_string(), Node::default()));
+        assert_eq!(paths(&mut app), entries(&[("B", true)]));
+
+        apply(&mut app, FamilyUndoEvent::undo());
+        assert_eq!(paths(&mut app), entries(&[("A", true)]));
+
+        apply(&mut app, FamilyUndoEvent::redo());
+        assert_eq!(paths(&mut app), entries(&[("B", true)]));
+    }
+
+    /// Get the identifiers of the children of the entity in the snapshot of the lineage.
+    fn snapshot_children(app: &App, identifier: &str) -> Vec<String> {
+        let snapshot = app.world().resource::<Lineage<String>>().snapshot();
+        snapshot.children(&Identifier(identifier.to_string())).map(|node| node.identifier.0.clone()).collect()
+    }
+
+    #[test]
+    fn undo_brings_back_the_deleted_descendants_with_their_values() {
+        let mut app = app();
+        send(&mut app, CudEvent::create_parent("A".to_string(), Node(1)));
+        send(&mut app, CudEvent::create_child("A".to_string(), "B".to_string(), Node(2)));
+        send(&mut app, CudEvent::create_child("B".to_string(), "C".to_string(), Node(3)));
+        send(&mut app, CudEvent::delete_parent("A".to_string(), Node::default()));
+        assert_eq!(paths(&mut app), []);
+
+        apply(&mut app, FamilyUndoEvent::undo());
+        assert_eq!(paths(&mut app), entries(&[("A", true), ("A/B", true), ("A/B/C", true)]));
+        for (identifier, value) in [("A", 1), ("B", 2), ("C", 3)] {
+            let entity = crate::testing::entity(&mut app, identifier);
+            assert_eq!(app.world().get::<Node>(entity), Some(&Node(value)));
+        }
+        assert_eq!(snapshot_children(&app, "A"), ["B"]);
+        assert_eq!(snapshot_children(&app, "B"), ["C"]);
+    }
+
+    #[test]
+    fn undo_brings_back_the_cleared_children_in_the_snapshot() {
+        let mut app = app();
+        send(&mut app, CudEvent::create_parent("A".to_string(), Node::default()));
+        send(&mut app, CudEvent::create_child("A".to_string(), "B".to_string(), Node::default()));
+        send(&mut app, CudEvent::clear_parent("A".to_string(), Node::default()));
+        assert_eq!(snapshot_children(&app, "A"), Vec::<String>::new());
+
+        apply(&mut app, FamilyUndoEvent::undo());
+        assert_eq!(paths(&mut app), entries(&[("A", true), ("A/B", true)]));
+        assert_eq!(snapshot_children(&app, "A"), ["B"]);
+    }
+
+    #[test]
+    fn groups_the_events_into_a_single_step() {
+        let mut app = app();
+        app.world_mut().resource_mut::<FamilyUndo<String>>().begin_group();
+        send(&mut app, CudEvent::create_parent("A".to_string(), Node::default()));
+        send(&mut app, CudEvent::create_child("A".to_string(), "B".to_string(), Node::default()));
+        app.world_mut().resource_mut::<FamilyUndo<String>>().end_group();
+        send(&mut app, CudEvent::update_child("A".to_string(), "B".to_string(), Node::default()));
+        assert_eq!(app.world().resource::<FamilyUndo<String>>().undo_len(), 2);
+
+        apply(&mut app, FamilyUndoEvent::undo());
+        assert_eq!(paths(&mut app), entries(&[("A", true), ("A/B", true)]));
+
+        apply(&mut app, FamilyUndoEvent::undo());
+        assert_eq!(paths(&mut app), []);
+        assert_eq!(app.world().resource::<FamilyUndo<String>>().redo_len(), 2);
+    }
+}